@@ -0,0 +1,158 @@
+// several programs at once, like 'runner --batch a.rs b.rs c.rs'. Each is handled by
+// its own runner, with the same flags: first they are all built in parallel,
+// and then they run one after the other. At the end there's a table of how
+// each did, and how long it took.
+use es::traits::*;
+use std::env;
+use std::fs;
+use std::path::{Path,PathBuf};
+use std::process;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize,Ordering};
+use std::thread;
+use std::time::{Duration,Instant};
+
+use crate::compile;
+
+// with --batch, every argument has to be a program
+pub fn is_program(file: &str) -> bool {
+    file.ends_with(".rs") && Path::new(file).is_file()
+}
+
+// 'runner --all dir' means every program in the directory, in order. Not env.rs,
+// which goes with all of them, nor the modules which programs there use
+pub fn programs_in(dir: &Path) -> Vec<String> {
+    let files: Vec<PathBuf> = fs::read_dir(dir)
+        .or_then_die(|e| format!("cannot read {}: {}",dir.display(),e))
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_file() && p.extension().is_some_and(|e| e == "rs"))
+        .collect();
+    let modules: Vec<PathBuf> = files.iter()
+        .flat_map(|p| fs::read_to_string(p).unwrap_or_default()
+            .lines()
+            .filter_map(|l| compile::module_name(l.trim()).map(|m| dir.join(m).with_extension("rs")))
+            .collect::<Vec<_>>())
+        .collect();
+    let mut res: Vec<String> = files.iter()
+        .filter(|p| ! p.ends_with("env.rs") && ! modules.contains(p))
+        .map(|p| p.display().to_string())
+        .collect();
+    res.sort();
+    res
+}
+
+// how a program did, for the summary
+struct Outcome<'a> {
+    file: &'a str,
+    build: (bool,Duration),
+    run: Option<(bool,Duration)>,
+}
+
+fn status(result: Option<(bool,Duration)>) -> String {
+    match result {
+        Some((ok,time)) => format!("{} {:.2}s",if ok {"ok"} else {"failed"},time.as_secs_f64()),
+        None => "-".to_string()
+    }
+}
+
+// with the headers, on stderr if the output is plain
+fn summary(outcomes: &[Outcome], plain: bool) {
+    let rows: Vec<[String; 3]> = outcomes.iter()
+        .map(|o| [o.file.to_string(), status(Some(o.build)), status(o.run)])
+        .collect();
+    let width = |i: usize| rows.iter().map(|r| r[i].len()).max().unwrap_or(0).max(5);
+    let (w0,w1) = (width(0),width(1));
+    let mut lines = vec![format!("{:<w0$}  {:<w1$}  {}","program","build","run")];
+    for r in &rows {
+        lines.push(format!("{:<w0$}  {:<w1$}  {}",r[0],r[1],r[2]));
+    }
+    let failed = outcomes.iter().filter(|o| ! o.build.0 || o.run.is_some_and(|(ok,_)| ! ok)).count();
+    lines.push(if failed == 0 {
+        format!("all {} programs passed",outcomes.len())
+    } else {
+        format!("{} of {} programs failed",failed,outcomes.len())
+    });
+    for line in lines {
+        if plain {
+            eprintln!("{}",line);
+        } else {
+            println!("{}",line);
+        }
+    }
+}
+
+fn runner(flags: &[String], extra: &str, file: &str) -> process::Command {
+    let mut builder = process::Command::new(env::current_exe().or_die("cannot find runner"));
+    builder.args(flags);
+    if ! extra.is_empty() {
+        builder.arg(extra);
+    }
+    builder.arg(file);
+    builder
+}
+
+// build in parallel, as many at a time as there are CPUs
+fn build_all(flags: &[String], files: &[String], extra: &str) -> Vec<(process::Output,Duration)> {
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new(Vec::new());
+    let threads = thread::available_parallelism().map(|n| n.get()).unwrap_or(1).min(files.len());
+    thread::scope(|s| {
+        for _ in 0..threads {
+            s.spawn(|| loop {
+                let i = next.fetch_add(1,Ordering::SeqCst);
+                if i >= files.len() {
+                    break;
+                }
+                let start = Instant::now();
+                let output = runner(flags, extra, &files[i]).output().or_die("cannot run runner");
+                results.lock().unwrap().push((i,(output,start.elapsed())));
+            });
+        }
+    });
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(i,_)| *i);
+    results.into_iter().map(|(_,result)| result).collect()
+}
+
+// flags are the command line without the programs, and any arguments after '--'
+// go to each program. Returns the exit code.
+// Plain output is just what the programs write, so the headers go to stderr
+pub fn run_programs(flags: Vec<String>, files: Vec<String>, passthrough: Vec<String>, keep_going: bool, compile_only: bool, plain: bool) -> i32 {
+    info!("building {} programs",files.len());
+    let outputs = build_all(&flags, &files, if compile_only {""} else {"--no-run"});
+    let mut failed = 0;
+    let mut outcomes = Vec::new();
+    for (file,(output,time)) in files.iter().zip(outputs) {
+        print!("{}",String::from_utf8_lossy(&output.stdout));
+        if ! output.status.success() {
+            eprintln!("==> {} failed to build <==",file);
+            failed += 1;
+        }
+        eprint!("{}",String::from_utf8_lossy(&output.stderr));
+        outcomes.push(Outcome { file, build: (output.status.success(),time), run: None });
+    }
+    if failed > 0 && ! keep_going {
+        summary(&outcomes, plain);
+        info!("nothing was run, since {} failed to build (use --keep-going to run the rest)",
+            if failed == 1 {"one"} else {"some"});
+        return 1;
+    }
+    if ! compile_only {
+        for o in outcomes.iter_mut().filter(|o| o.build.0) {
+            if plain {
+                eprintln!("==> {} <==",o.file);
+            } else {
+                println!("==> {} <==",o.file);
+            }
+            let start = Instant::now();
+            let status = runner(&flags, "--run", o.file).arg("--").args(&passthrough).status().or_die("cannot run runner");
+            if ! status.success() {
+                failed += 1;
+            }
+            o.run = Some((status.success(),start.elapsed()));
+        }
+    }
+    summary(&outcomes, plain);
+    if failed > 0 {1} else {0}
+}
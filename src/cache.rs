@@ -5,18 +5,30 @@ use std::process;
 use std::env;
 use std::fs;
 use std::path::{Path,PathBuf};
-use std::collections::HashMap;
-use std::io::Write;
+use std::collections::{HashMap,HashSet};
+use std::io::{self,Write};
+use std::time::{Duration,Instant,SystemTime};
+use std::sync::atomic::{AtomicBool,Ordering};
+use std::sync::{Mutex,OnceLock};
 
 use crate::crate_utils;
 use crate::meta;
+use crate::cargo_lock;
+use crate::logging;
+use crate::strutil;
 
 use crate_utils::UNSTABLE;
 
 use crate::state::State;
 
-const STATIC_CACHE: &str = "static-cache";
+pub const STATIC_CACHE: &str = "static-cache";
 const DYNAMIC_CACHE: &str = "dy-cache";
+const INCREMENTAL: &str = "incremental";
+const LINK_PLANS: &str = "link-plans";
+const STORE: &str = "store";
+
+// incremental state for a snippet is kept for a week after it was last built
+const INCREMENTAL_DAYS: u64 = 7;
 
 // this will be initially written to ~/.cargo/.runner/prelude and
 // can then be edited.
@@ -44,35 +56,175 @@ macro_rules! debug {
 // cf. https://github.com/brson/stdx
 const KITCHEN_SINK: &str = "
     chrono
+    rayon
     regex
     serde_json
     serde_yaml
 ";
 
-// Windows shell quoting is a mess, so we make single quotes
-// become double quotes in expressions
+// --raw leaves expressions exactly as they are
+static RAW: AtomicBool = AtomicBool::new(false);
+
+pub fn set_raw(raw: bool) {
+    RAW.store(raw, Ordering::Relaxed);
+}
+
+// more arguments for cargo when building the static cache, like '--jobs 2' or '--offline'
+static CARGO_ARGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+pub fn set_cargo_args(args: Vec<String>) {
+    *CARGO_ARGS.lock().unwrap() = args;
+}
+
+// Windows shell quoting is a mess, so we make single-quoted strings
+// become double-quoted in expressions
 pub fn quote(s: String) -> String {
-    if cfg!(all(windows, not(feature = "no_quote_replacement"))) {
-        s.replace("\'","\"")
+    if cfg!(all(windows, not(feature = "no_quote_replacement"))) && ! RAW.load(Ordering::Relaxed) {
+        strutil::rewrite_quotes(&s)
     } else {
         s
     }
 }
 
+// RUNNER_HOME overrides the usual ~/.cargo/.runner, and RUNNER_XDG
+// asks for the XDG data directory (e.g. ~/.local/share/runner)
+pub fn base_directory() -> PathBuf {
+    if let Ok(home) = env::var("RUNNER_HOME") {
+        PathBuf::from(home)
+    } else if env::var("RUNNER_XDG").is_ok() {
+        dirs::data_dir().or_die("no XDG data directory").join("runner")
+    } else {
+        crate_utils::cargo_home().join(".runner")
+    }
+}
+
+// nightly gets its own directory. (Finding out means asking rustc,
+// so the daemon uses base_directory instead)
 pub fn runner_directory() -> PathBuf {
-    let mut runner = crate_utils::cargo_home().join(".runner");
+    let mut runner = base_directory();
     if *UNSTABLE {
         runner.push("unstable");
     }
     runner
 }
 
+// cargo always works in the static cache (or the runner directory, before there
+// is one), so that it finds the same registries, mirrors and credentials in
+// ~/.cargo/config.toml and any .cargo/config.toml above, wherever runner is run
+pub fn cargo_command() -> process::Command {
+    let mut c = process::Command::new("cargo");
+    let static_cache = static_cache_dir();
+    c.current_dir(if static_cache.is_dir() {static_cache} else {runner_directory()});
+    c
+}
+
 pub fn cargo(args: &[&str]) -> bool {
-    let res = process::Command::new("cargo")
-        .args(args)
-        .status()
-        .or_die("can't run cargo");
-    res.success()
+    trace!("cargo {}",args.join(" "));
+    let mut c = cargo_command();
+    c.args(args);
+    if logging::dry_run_command(&c) {
+        return true;
+    }
+    c.status().or_die("can't run cargo").success()
+}
+
+// a package which 'cargo update' would change: no old version if it would
+// be added, and no new version if it would be removed
+pub struct UpdateChange {
+    pub package: String,
+    pub from: Option<String>,
+    pub to: Option<String>,
+}
+
+// what 'cargo update' would do to the static cache's Cargo.lock, without doing it.
+// cargo reports on stderr, with lines like 'Updating syn v2.0.8 -> v2.0.9'
+pub fn update_dry_run(package: Option<&str>) -> Vec<UpdateChange> {
+    let mut c = cargo_command();
+    c.args(["update","--dry-run","--color","never"]);
+    if let Some(package) = package {
+        c.args(["--package",package]);
+    }
+    trace!("{}",logging::shell_command(&c));
+    let o = c.output().or_die("can't run cargo");
+    let text = String::from_utf8_lossy(&o.stderr);
+    if ! o.status.success() {
+        es::quit(&format!("cargo update failed:\n{}",text.trim_end()));
+    }
+    let version = |v: &str| v.trim_start_matches('v').to_string();
+    let mut changes = Vec::new();
+    for line in text.lines() {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let change = match words.as_slice() {
+            ["Updating",package,from,"->",to,..] | ["Downgrading",package,from,"->",to,..] =>
+                UpdateChange{package: package.to_string(), from: Some(version(from)), to: Some(version(to))},
+            ["Adding",package,to,..] =>
+                UpdateChange{package: package.to_string(), from: None, to: Some(version(to))},
+            ["Removing",package,from,..] =>
+                UpdateChange{package: package.to_string(), from: Some(version(from)), to: None},
+            _ => continue
+        };
+        changes.push(change);
+    }
+    changes
+}
+
+// live progress for static cache builds: how many crates are built out of
+// those in Cargo.lock, which crate was last built and how long it's taking
+struct Progress {
+    mode: &'static str,
+    start: Instant,
+    total: usize,
+    built: HashSet<String>,
+    live: bool,
+}
+
+impl Progress {
+    fn new(release: bool) -> Progress {
+        Progress {
+            mode: if release {"release"} else {"debug"},
+            start: Instant::now(),
+            total: 0,
+            built: HashSet::new(),
+            live: isatty::stderr_isatty() && logging::enabled(logging::NORMAL),
+        }
+    }
+
+    fn clear_line(&self) {
+        if self.live {
+            eprint!("\r\x1b[K");
+        }
+    }
+
+    fn update(&mut self, doc: &json::JsonValue) {
+        let is_build_script = doc["target"]["kind"].members().any(|k| k == "custom-build");
+        if doc["reason"] != "compiler-artifact" || is_build_script {
+            return;
+        }
+        let (name,_) = meta::package_name_version(doc["package_id"].as_str().unwrap_or(""));
+        self.built.insert(doc["package_id"].to_string());
+        if self.total == 0 {
+            // Cargo.lock has been written by the time artifacts appear
+            let lockf = static_cache_dir().join("Cargo.lock");
+            if lockf.is_file() {
+                self.total = cargo_lock::read_cargo_lock(&static_cache_dir()).package.len();
+            }
+        }
+        let total = self.total.max(self.built.len());
+        if self.live {
+            self.clear_line();
+            eprint!("[{}/{}] {} {} ({}s)",self.built.len(),total,self.mode,name,self.start.elapsed().as_secs());
+            io::stderr().flush().or_die("i/o?");
+        } else {
+            verbose!("[{}/{}] {} {}",self.built.len(),total,self.mode,name);
+        }
+    }
+
+    fn finish(&self, ok: bool) {
+        self.clear_line();
+        if ok {
+            info!("built {} crates ({}) in {:.1}s",self.built.len(),self.mode,self.start.elapsed().as_secs_f32());
+        }
+    }
 }
 
 pub fn cargo_build(release: bool) -> Option<String> {
@@ -80,13 +232,23 @@ pub fn cargo_build(release: bool) -> Option<String> {
     use std::io::BufReader;
     use std::io::prelude::*;
 
-    let mut c = process::Command::new("cargo");
+    let mut progress = Progress::new(release);
+    let mut c = cargo_command();
     c.arg("build");
     if release {
         c.arg("--release");
     }
-    c.stdout(Stdio::piped());
+    if progress.live {
+        // the progress display replaces cargo's own chatter
+        c.arg("--quiet");
+        info!("building static cache ({})...",progress.mode);
+    }
     c.arg("--message-format").arg("json");
+    c.args(CARGO_ARGS.lock().unwrap().iter());
+    if logging::dry_run_command(&c) {
+        return Some(String::new());
+    }
+    c.stdout(Stdio::piped());
 
     let mut res = c.spawn().or_die("can't run cargo");
 
@@ -97,15 +259,26 @@ pub fn cargo_build(release: bool) -> Option<String> {
     for line in inb.lines() {
         if let Ok(line) = line {
             if line.starts_with('{') {
+                if let Ok(doc) = json::parse(&line) {
+                    // compile errors are otherwise buried in the JSON
+                    if doc["reason"] == "compiler-message" && doc["message"]["level"] == "error" {
+                        progress.clear_line();
+                        eprint!("{}",doc["message"]["rendered"]);
+                    }
+                    progress.update(&doc);
+                }
                 out += &line;
                 out.push('\n');
             } else {
+                progress.clear_line();
                 println!("{}",line);
             }
         }
     }
 
-    if res.wait().or_die("cargo build error").success() {
+    let ok = res.wait().or_die("cargo build error").success();
+    progress.finish(ok);
+    if ok {
         Some(out)
     } else {
         None
@@ -116,9 +289,256 @@ pub fn static_cache_dir() -> PathBuf {
     runner_directory().join(STATIC_CACHE)
 }
 
-pub fn get_metadata() -> meta::Meta {
+// cargo keeps the 'rustc -vV' it built with in target/.rustc_info.json
+pub fn static_cache_rustc() -> Option<String> {
+    let info = static_cache_dir().join("target").join(".rustc_info.json");
+    let doc = json::parse(&fs::read_to_string(info).ok()?).ok()?;
+    let version = doc["outputs"].entries()
+        .filter_map(|(_,o)| o["stdout"].as_str())
+        .find(|s| s.starts_with("rustc "))?
+        .lines().next()?.to_string();
+    Some(version)
+}
+
+// in the dynamic cache, the 'rustc --version' which built it
+pub const RUSTC_VERSION: &str = "rustc-version";
+
+// and the edition each of its crates was built with, as 'crate=edition' lines
+fn dynamic_editions_file() -> PathBuf {
+    dynamic_cache_dir().join("editions")
+}
+
+pub fn dynamic_editions() -> HashMap<String,String> {
+    fs::read_to_string(dynamic_editions_file()).unwrap_or_default().lines()
+        .filter_map(|s| s.split_at_delim('=').trim())
+        .to_map()
+}
+
+pub fn set_dynamic_edition(crate_name: &str, edition: &str) {
+    let mut editions = dynamic_editions();
+    editions.insert(crate_name.into(), edition.into());
+    let mut lines: Vec<String> = editions.into_iter().map(|(c,e)| format!("{}={}\n",c,e)).collect();
+    lines.sort();
+    fs::write(dynamic_editions_file(), lines.concat()).or_die("cannot write to dynamic cache");
+}
+
+// the versions asked for with '--add name@version' or 'name=version', which
+// --update keeps to until --unpin releases them
+fn pins_file() -> PathBuf {
+    static_cache_dir().join("pins")
+}
+
+pub fn pins() -> HashMap<String,String> {
+    fs::read_to_string(pins_file()).unwrap_or_default().lines()
+        .filter_map(|s| s.split_at_delim('=').trim())
+        .to_map()
+}
+
+fn write_pins(pins: HashMap<String,String>) {
+    let mut lines: Vec<String> = pins.into_iter().map(|(c,v)| format!("{}={}\n",c,v)).collect();
+    lines.sort();
+    fs::write(pins_file(), lines.concat()).or_die("cannot write to static cache");
+}
+
+// 'serde@1.0.200' is that exact version, and 'regex@^1.10' is a version requirement
+fn parse_pin(spec: &str) -> Option<(String,String)> {
+    let (name,vs) = spec.split_at_delim('@')?;
+    if name.is_empty() || vs.is_empty() || ! name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+        return None;
+    }
+    let vs = if vs.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("={}",vs)
+    } else {
+        vs.to_string()
+    };
+    Some((name.to_string(),vs))
+}
+
+// a version in Cargo.toml, from crates.io or another registry
+fn version_dependency(vs: &str, registry: Option<&str>) -> String {
+    match registry {
+        Some(registry) => format!("{{version=\"{}\",registry=\"{}\"}}",vs,registry),
+        None => format!("\"{}\"",vs)
+    }
+}
+
+// the registry a crate in Cargo.toml comes from, if not crates.io
+fn dependency_registry(toml: &str, name: &str) -> Option<String> {
+    let toml = toml.parse::<toml::Value>().ok()?;
+    let registry = toml.get("dependencies")?.get(name)?.get("registry")?;
+    registry.as_str().map(|r| r.to_string())
+}
+
+// where the crate's line is in the [dependencies] of Cargo.toml
+fn dependency_line(lines: &[String], name: &str) -> Option<usize> {
+    let mut section = "";
+    for (i,l) in lines.iter().enumerate() {
+        let trimmed = l.trim();
+        if trimmed.starts_with('[') {
+            section = trimmed;
+        } else if section == "[dependencies]" {
+            if let Some((key,_)) = trimmed.split_at_delim('=') {
+                if key.trim().trim_matches('"') == name {
+                    return Some(i);
+                }
+            }
+        }
+    }
+    None
+}
+
+// replace the crate's line in the [dependencies] of Cargo.toml, or add one
+fn set_dependency(toml: &str, name: &str, value: &str) -> String {
+    let mut lines: Vec<String> = toml.lines().map(|l| l.to_string()).collect();
+    let line = format!("{}={}",name,value);
+    match dependency_line(&lines, name) {
+        Some(i) => lines[i] = line,
+        None => lines.push(line)
+    }
+    lines.join("\n") + "\n"
+}
+
+// take a crate out of the static cache's Cargo.toml, and rebuild it
+pub fn remove_crate(name: &str) {
+    let static_cache = static_cache_dir_check();
+    let toml_file = static_cache.join("Cargo.toml");
+    let toml = fs::read_to_string(&toml_file).or_die("cannot read static cache Cargo.toml");
+    let mut lines: Vec<String> = toml.lines().map(|l| l.to_string()).collect();
+    let i = match dependency_line(&lines, name) {
+        Some(i) => i,
+        None => es::quit(&format!("{} is not in the static cache's Cargo.toml (if another crate needs it, 'runner --why {}' says which)",name,name))
+    };
+    env::set_current_dir(&static_cache).or_die("static cache wasn't a directory?");
+    if logging::dry_run() {
+        println!("# remove {} from {}",name,toml_file.display());
+        build_static_cache();
+        return;
+    }
+    lines.remove(i);
+    fs::write(&toml_file, lines.join("\n") + "\n").or_die("cannot write static cache Cargo.toml");
+    let mut pins = pins();
+    if pins.remove(name).is_some() {
+        write_pins(pins);
+    }
+    build_static_cache();
+}
+
+// say which pins --update keeps to (all of them, or the package's)
+pub fn keep_pins(package: Option<&str>) {
+    let mut pins: Vec<String> = pins().into_iter()
+        .filter(|(name,_)| package.is_none_or(|p| p == name))
+        .map(|(name,vs)| format!("{} {}",name,vs))
+        .collect();
+    if pins.is_empty() {
+        return;
+    }
+    pins.sort();
+    info!("keeping to {} (--unpin releases {})",pins.join(", "),if pins.len() == 1 {"it"} else {"them"});
+}
+
+// let --update move pinned crates (all of them, or the package) on to the latest version
+pub fn unpin(package: Option<&str>) {
+    let mut pins = pins();
+    let names: Vec<String> = match package {
+        Some(p) if ! pins.contains_key(p) => es::quit(&format!("{} is not pinned",p)),
+        Some(p) => vec![p.to_string()],
+        None => pins.keys().cloned().collect()
+    };
+    let toml_file = static_cache_dir().join("Cargo.toml");
+    let mut toml = fs::read_to_string(&toml_file).or_die("cannot read static cache Cargo.toml");
+    for name in &names {
+        let vs = pins.remove(name).unwrap();
+        if logging::dry_run() {
+            println!("# unpin {} {} in {}",name,vs,toml_file.display());
+        } else {
+            let value = version_dependency("*", dependency_registry(&toml, name).as_deref());
+            toml = set_dependency(&toml, name, &value);
+            info!("unpinned {} (was {})",name,vs);
+        }
+    }
+    if ! logging::dry_run() {
+        fs::write(&toml_file, toml).or_die("cannot write static cache Cargo.toml");
+        write_pins(pins);
+    }
+}
+
+// all the files in a directory and below, in bytes
+pub fn dir_size(dir: &Path) -> u64 {
+    fs::read_dir(dir).map(|entries| entries.flatten().map(|e| {
+        let path = e.path();
+        if path.is_dir() {
+            dir_size(&path)
+        } else {
+            e.metadata().map(|m| m.len()).unwrap_or(0)
+        }
+    }).sum()).unwrap_or(0)
+}
+
+// on Windows, each toolchain ABI gets its own, like dy-cache-msvc
+pub fn dynamic_cache_dir() -> PathBuf {
+    match crate_utils::windows_abi() {
+        Some(abi) => runner_directory().join(format!("{}-{}",DYNAMIC_CACHE,abi)),
+        None => runner_directory().join(DYNAMIC_CACHE)
+    }
+}
+
+// and on Windows the target it was built for, which must be what rustc builds for now
+const HOST_FILE: &str = "host";
+
+pub fn set_dynamic_host() {
+    if crate_utils::windows_abi().is_none() {
+        return;
+    }
+    fs::write(dynamic_cache_dir().join(HOST_FILE), &*crate_utils::HOST).or_die("cannot write to dynamic cache");
+}
+
+pub fn check_dynamic_host() {
+    if crate_utils::windows_abi().is_none() {
+        return;
+    }
+    let dir = dynamic_cache_dir();
+    if let Ok(host) = fs::read_to_string(dir.join(HOST_FILE)) {
+        if host.trim() != *crate_utils::HOST {
+            es::quit(&format!("the dynamic cache {} was built for {}, but rustc builds for {}: move it aside and rebuild its crates with -C",
+                dir.display(),host.trim(),*crate_utils::HOST));
+        }
+    }
+}
+
+// files which the daemon has already read (or parsed) for its children, with when
+// they were last changed, so that they are read again if they have been since
+static WARM_METADATA: OnceLock<(PathBuf,SystemTime,meta::Meta)> = OnceLock::new();
+static WARM_PRELUDE: OnceLock<(PathBuf,SystemTime,String)> = OnceLock::new();
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+fn keep_warm<T>(warm: &OnceLock<(PathBuf,SystemTime,T)>, path: &Path, value: T) {
+    if let Some(time) = modified(path) {
+        let _ = warm.set((path.to_path_buf(),time,value));
+    }
+}
+
+fn still_warm<T: Clone>(warm: &OnceLock<(PathBuf,SystemTime,T)>, path: &Path) -> Option<T> {
+    let (file,time,value) = warm.get()?;
+    (file == path && modified(path).as_ref() == Some(time)).then(|| value.clone())
+}
+
+// the daemon does this once, before it starts serving
+pub fn warm_up() {
+    keep_warm(&WARM_PRELUDE, &runner_directory().join("prelude"), get_prelude());
     let static_cache = static_cache_dir();
     if meta::Meta::exists(&static_cache) {
+        keep_warm(&WARM_METADATA, &meta::file_name(&static_cache), meta::Meta::new_from_file(&static_cache));
+    }
+}
+
+pub fn get_metadata() -> meta::Meta {
+    let static_cache = static_cache_dir();
+    if let Some(m) = still_warm(&WARM_METADATA, &meta::file_name(&static_cache)) {
+        m
+    } else if meta::Meta::exists(&static_cache) {
         meta::Meta::new_from_file(&static_cache)
     } else {
         es::quit("please build the static cache with `runner --add <crate>...` first");
@@ -144,13 +564,20 @@ pub fn build_static_cache() -> bool {
         None => return false,
         Some(s) => m.release(s)
     }
-    m.update(&static_cache_dir());
-    cargo(&["doc"])
+    if ! logging::dry_run() {
+        cargo_lock::check(&static_cache_dir(), &m);
+        m.update(&static_cache_dir());
+    }
+    if isatty::stderr_isatty() && logging::enabled(logging::NORMAL) {
+        info!("building documentation...");
+        cargo(&["doc","--quiet"])
+    } else {
+        cargo(&["doc"])
+    }
 }
 
-pub fn create_static_cache(crates: &[String]) {
-    use std::io::prelude::*;
-
+// crates with versions come from the registry, if given, rather than crates.io
+pub fn create_static_cache(crates: &[String], registry: Option<&str>) {
     let static_cache = static_cache_dir();
     let exists = static_cache.exists();
 
@@ -177,15 +604,18 @@ pub fn create_static_cache(crates: &[String]) {
         false
     }; 
 
-    // there are three forms possible
+    // there are four forms possible
     // a plain crate name - we assume latest version ('*')
     // a name=vs - we'll ensure it gets quoted properly
+    // a name@vs - an exact version, unless it's a requirement like '^1.10'
     // a local Cargo project
     let crates_vs = crates.iter().filter_map(|c| {
         if let Some(idx) = c.find('=') {
             // help with a little bit of quoting...
             let (name,vs) = (&c[0..idx], &c[(idx+1)..]);
             Some((name.to_string(),vs.to_string(),true))
+        } else if let Some((name,vs)) = parse_pin(c) {
+            Some((name,vs,true))
         } else {
             // explicit name but no version, see if we already have this crate
             if let Some((name,path)) = maybe_cargo_dir(&c) {
@@ -210,26 +640,137 @@ pub fn create_static_cache(crates: &[String]) {
     }
 
     home.push(STATIC_CACHE);
+    if logging::dry_run() {
+        for (name,vs,_) in crates_vs {
+            println!("# add {} {} to {}",name,vs,home.join("Cargo.toml").display());
+        }
+        build_static_cache();
+        return;
+    }
     env::set_current_dir(&home).or_die("could not change to static cache directory");
     let tmpfile = env::temp_dir().join("Cargo.toml");
     fs::copy("Cargo.toml",&tmpfile).or_die("cannot back up Cargo.toml");
-    {
-        let mut deps = fs::OpenOptions::new().append(true)
-            .open("Cargo.toml").or_die("could not append to Cargo.toml");
-        for (name,vs,semver) in crates_vs {
-            if semver {
-                write!(deps,"{}=\"{}\"\n",name,vs)
-            } else {
-               write!(deps,"{}={{path=\"{}\"}}\n",name,vs)
-            }.or_die("could not modify Cargo.toml");
-        }
+    // a crate which is already there gets the new version
+    let mut toml = fs::read_to_string("Cargo.toml").or_die("could not read Cargo.toml");
+    for (name,vs,semver) in &crates_vs {
+        let value = if *semver {
+            version_dependency(vs, registry)
+        } else {
+            format!("{{path=\"{}\"}}",vs)
+        };
+        toml = set_dependency(&toml, name, &value);
     }
+    fs::write("Cargo.toml", toml).or_die("could not modify Cargo.toml");
     if ! build_static_cache() {
-        println!("Error occurred - restoring Cargo.toml");
+        info!("Error occurred - restoring Cargo.toml");
         fs::copy(&tmpfile,"Cargo.toml").or_die("cannot restore Cargo.toml");
+        return;
+    }
+    let mut pins = pins();
+    for (name,vs,semver) in crates_vs {
+        if semver && vs != "*" {
+            pins.insert(name, vs);
+        } else {
+            pins.remove(&name);
+        }
+    }
+    write_pins(pins);
+}
+
+// crates wanted by a program which are not in the static cache are added on the fly,
+// either because of --auto-add or because the user says so when asked.
+// Anything still missing is reported later by rustc_command
+pub fn add_missing_crates(crates: &[String], auto_add: bool, registry: Option<&str>) {
+    let mut missing = unaliased(crates);
+    if static_cache_dir().exists() {
+        let m = get_metadata();
+        missing.retain(|c| ! m.is_crate_present(c));
+    }
+    missing.sort();
+    missing.dedup();
+    if missing.is_empty() {
+        return;
+    }
+    let question = format!("{} not in the static cache. Add now?",missing.join(", "));
+    if agreed(&question, auto_add) {
+        add_crates(&missing, registry);
+    }
+}
+
+// asked only if there's someone to answer
+fn agreed(question: &str, auto_add: bool) -> bool {
+    if auto_add {
+        return true;
+    }
+    if ! isatty::stdin_isatty() {
+        return false;
+    }
+    eprint!("{} [y/N] ",question);
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer).or_die("cannot read answer");
+    answer.trim().eq_ignore_ascii_case("y")
+}
+
+fn add_crates(crates: &[String], registry: Option<&str>) {
+    info!("adding {} to the static cache",crates.join(" "));
+    // which changes directory to do its work
+    let here = env::current_dir().or_die("cannot get current directory");
+    create_static_cache(crates, registry);
+    env::set_current_dir(&here).or_die("cannot change back to current directory");
+}
+
+// the name a crate has in the registry, which may have '-' where the code
+// has '_'. None if there's no such crate (so it's probably a module); if
+// the registry can't be asked, the name as it is
+fn registry_name(name: &str, registry: Option<&str>) -> Option<String> {
+    let mut c = process::Command::new("cargo");
+    c.args(["search","--limit","1",name]);
+    if let Some(registry) = registry {
+        c.args(["--registry",registry]);
+    }
+    let output = match c.output() {
+        Ok(output) if output.status.success() => output,
+        _ => return Some(name.to_string())
+    };
+    let text = String::from_utf8_lossy(&output.stdout);
+    let found = text.lines().next()?.split_whitespace().next()?;
+    if found.replace('-',"_") == name.replace('-',"_") {
+        Some(found.to_string())
+    } else {
+        None
     }
 }
 
+// after a build fails because rustc could not find these crates, offer to
+// add them to the static cache. Returns the crates now there, so the build
+// can be tried again with them
+pub fn add_crates_rustc_wanted(names: &[String], auto_add: bool, registry: Option<&str>) -> Vec<String> {
+    // the registry is only asked if there's someone to ask
+    if names.is_empty() || ! (auto_add || isatty::stdin_isatty()) {
+        return Vec::new();
+    }
+    let m = if static_cache_dir().exists() {Some(get_metadata())} else {None};
+    let mut wanted: Vec<String> = unaliased(names).iter()
+        .filter(|c| ! m.as_ref().is_some_and(|m| m.is_crate_present(c)))
+        .filter_map(|c| registry_name(c, registry))
+        .collect();
+    wanted.sort();
+    wanted.dedup();
+    if wanted.is_empty() {
+        return Vec::new();
+    }
+    let quoted: Vec<String> = wanted.iter().map(|c| format!("`{}`",c)).collect();
+    if ! agreed(&format!("add {} to the static cache and retry?",quoted.join(", ")), auto_add) {
+        return Vec::new();
+    }
+    add_crates(&wanted, registry);
+    let m = get_metadata();
+    wanted.into_iter()
+        .map(|c| c.replace('-',"_"))
+        .filter(|c| m.is_crate_present(c))
+        .collect()
+}
+
 fn maybe_cargo_dir(name: &str) -> Option<(String,PathBuf)> {
     let path = Path::new(name);
     if ! path.exists() || ! path.is_dir() {
@@ -256,12 +797,17 @@ pub fn get_prelude() -> String {
     let bin = home.join("bin");
     if pristine {
         fs::write(&prelude,PRELUDE).or_die("cannot write prelude");
-        fs::create_dir(&home.join(DYNAMIC_CACHE)).or_die("cannot create dynamic cache");
+    }
+    // which may be new for this toolchain on Windows
+    let dynamic_cache = dynamic_cache_dir();
+    if ! dynamic_cache.is_dir() {
+        fs::create_dir(&dynamic_cache).or_die("cannot create dynamic cache");
     }
     if pristine || ! bin.is_dir() {
         fs::create_dir(&bin).or_die("cannot create output directory");
     }
-    fs::read_to_string(&prelude).or_die("cannot read prelude")
+    still_warm(&WARM_PRELUDE, &prelude)
+        .unwrap_or_else(|| fs::read_to_string(&prelude).or_die("cannot read prelude"))
 }
 
 pub fn get_cache(state: &State) -> PathBuf {
@@ -272,11 +818,121 @@ pub fn get_cache(state: &State) -> PathBuf {
         home.push(if state.optimize {"release"} else {"debug"});
         home.push("deps");
     } else {
-        home.push(DYNAMIC_CACHE);
+        home = dynamic_cache_dir();
     };
     home
 }
 
+// a file kept next to a program, like 'a.x.stamp' for 'a.x' (so that 'a.x' and
+// 'a.y' don't share one), but 'a.stamp' for 'a.exe'
+pub fn beside(program: &Path, ext: &str) -> PathBuf {
+    let name = program.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let name = name.strip_suffix(env::consts::EXE_SUFFIX).unwrap_or(name);
+    program.with_file_name(format!("{}.{}",name,ext))
+}
+
+// take an exclusive lock while a program is being written and compiled, so that
+// runner invocations working in parallel don't clobber each other's files.
+// The lock is released when the file is dropped.
+pub fn lock_program(program: &Path) -> fs::File {
+    let lockf = beside(program, "lock");
+    let f = fs::OpenOptions::new().create(true).write(true).truncate(false)
+        .open(&lockf).or_die("cannot create lock file");
+    f.lock().or_die("cannot lock program");
+    f
+}
+
+// next to each program, a note of how it was built, and the file it was
+// built from (expressions have none)
+pub fn record_build(program: &Path, source: Option<&Path>, mode: &str) {
+    let mut text = format!("mode={}\n",mode);
+    if let Some(source) = source {
+        let source = fs::canonicalize(source).unwrap_or_else(|_| source.to_path_buf());
+        text += &format!("source={}\n",source.display());
+    }
+    let record = beside(program, "build");
+    if let Err(e) = fs::write(&record, text) {
+        verbose!("cannot write {}: {}",record.display(),e);
+    }
+}
+
+fn build_record(program: &Path, key: &str) -> Option<String> {
+    let record = fs::read_to_string(beside(program, "build")).ok()?;
+    let key = format!("{}=",key);
+    record.lines()
+        .find_map(|line| line.strip_prefix(key.as_str()))
+        .map(|value| value.to_string())
+}
+
+// None for expressions, and programs built by older runners
+pub fn recorded_source(program: &Path) -> Option<PathBuf> {
+    build_record(program, "source").map(PathBuf::from)
+}
+
+// like 'static optimized'
+pub fn recorded_mode(program: &Path) -> Option<String> {
+    build_record(program, "mode")
+}
+
+// rustc's incremental state for a snippet, keyed by its path and how it's built.
+// Old state for other snippets is cleared out at the same time
+pub fn incremental_dir(key: &str) -> PathBuf {
+    let dir = runner_directory().join(INCREMENTAL);
+    let max_age = Duration::from_secs(INCREMENTAL_DAYS*24*60*60);
+    if let Ok(entries) = fs::read_dir(&dir) {
+        for entry in entries.flatten() {
+            let age = entry.metadata().and_then(|m| m.modified()).ok().and_then(|t| t.elapsed().ok());
+            if age.is_some_and(|age| age > max_age) {
+                verbose!("removing old incremental state {}",entry.path().display());
+                let _ = fs::remove_dir_all(entry.path());
+            }
+        }
+    }
+    dir.join(strutil::content_hash(&[key]))
+}
+
+// where a dynamic build's --extern arguments are kept. The key includes when the
+// dynamic cache and the aliases last changed, so a plan is not used after they do
+// (every crate built into the dynamic cache also writes its rustc version)
+pub fn link_plan_file(key: &str) -> PathBuf {
+    let changed = |path: PathBuf| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let dynamic_cache = dynamic_cache_dir();
+    let stamp = format!("{:?} {:?} {:?}",changed(dynamic_cache.clone()),changed(dynamic_cache.join(RUSTC_VERSION)),
+        changed(runner_directory().join("alias")));
+    runner_directory().join(LINK_PLANS).join(strutil::content_hash(&[key,&stamp]))
+}
+
+// compiled snippets are kept in the store by what went into them: the generated
+// code, how it was built, the rustc which built it, and when the cache it links
+// against last changed. So the same snippet from another directory, or under
+// another name, is linked to rather than built again
+pub fn store_path(parts: &[&str], state: &State) -> PathBuf {
+    let changed = |path: PathBuf| fs::metadata(path).and_then(|m| m.modified()).ok();
+    let cache = get_cache(state);
+    let stamp = format!("{} {:?} {:?}",*crate_utils::RUSTC_RELEASE,changed(cache.clone()),changed(cache.join(RUSTC_VERSION)));
+    let mut parts = parts.to_vec();
+    parts.push(&stamp);
+    runner_directory().join(STORE).join(format!("{}{}",strutil::content_hash(&parts),env::consts::EXE_SUFFIX))
+}
+
+// a hard link if possible, otherwise a copy
+fn link_or_copy(from: &Path, to: &Path) -> io::Result<()> {
+    let _ = fs::remove_file(to);
+    fs::hard_link(from, to).or_else(|_| fs::copy(from, to).map(|_| ()))
+}
+
+pub fn fetch_from_store(stored: &Path, program: &Path) {
+    verbose!("{} is already built as {}",program.display(),stored.display());
+    link_or_copy(stored, program).or_then_die(|e| format!("cannot link {} to {}: {}",program.display(),stored.display(),e));
+}
+
+pub fn put_in_store(program: &Path, stored: &Path) {
+    let res = fs::create_dir_all(stored.parent().unwrap()).and_then(|_| link_or_copy(program, stored));
+    if let Err(e) = res {
+        verbose!("cannot keep {} in the store: {}",program.display(),e);
+    }
+}
+
 pub fn add_aliases(aliases: Vec<String>) {
     if aliases.len() == 0 { return; }
     let alias_file = runner_directory().join("alias");
@@ -286,11 +942,87 @@ pub fn add_aliases(aliases: Vec<String>) {
         fs::File::create(&alias_file)
     }.or_die("cannot open runner alias file");
 
-    for crate_alias in aliases {
+    // values are split at spaces, so a bundle like 'data=-x polars -x serde_json'
+    // arrives in pieces, and everything up to the next 'name=' belongs to it
+    let mut lines: Vec<String> = Vec::new();
+    for a in aliases {
+        match lines.last_mut() {
+            Some(last) if is_bundle(last) && ! is_entry(&a) => {
+                last.push(' ');
+                last.push_str(&a);
+            },
+            _ => lines.push(a)
+        }
+    }
+    for crate_alias in lines {
         write!(f,"{}\n",crate_alias).or_die("cannot write to runner alias file");
     }
 }
 
+// rewrites the alias file without this name. False if it wasn't there
+pub fn remove_alias(name: &str) -> bool {
+    let alias_file = runner_directory().join("alias");
+    if ! alias_file.is_file() { return false; }
+    let contents = fs::read_to_string(&alias_file).or_die("cannot read alias file");
+    let (removed, kept): (Vec<&str>,Vec<&str>) = contents.lines()
+        .partition(|s| s.split('=').next().map(str::trim) == Some(name));
+    if removed.is_empty() { return false; }
+    let mut kept = kept.join("\n");
+    if ! kept.is_empty() {
+        kept.push('\n');
+    }
+    fs::write(&alias_file,kept).or_die("cannot write to runner alias file");
+    true
+}
+
+// like 'data=...', rather than part of a bundle
+fn is_entry(alias: &str) -> bool {
+    alias.split_once('=').is_some_and(|(name,_)| ! name.is_empty()
+        && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') && ! name.starts_with('-'))
+}
+
+fn is_bundle(alias: &str) -> bool {
+    alias.split_once('=').is_some_and(|(_,value)| value.trim().starts_with('-'))
+}
+
+// an alias can also stand for several flags, like 'data=-x polars -x serde_json',
+// and then '-x data' is replaced by those flags
+pub fn expand_bundles(args: Vec<String>) -> Vec<String> {
+    let bundles: HashMap<String,String> = get_aliases().into_iter()
+        .filter(|(_,value)| value.starts_with('-'))
+        .collect();
+    if bundles.is_empty() {
+        return args;
+    }
+    let mut res = Vec::new();
+    let mut iter = args.into_iter();
+    while let Some(a) = iter.next() {
+        let name = if a == "-x" || a == "--extern" {
+            iter.next()
+        } else if a.len() > 2 && a.starts_with("-x") {
+            Some(a[2..].to_string())
+        } else {
+            None
+        };
+        match name {
+            Some(name) => if let Some(bundle) = bundles.get(&name) {
+                res.extend(shlex::split(bundle).or_then_die(|_| format!("bad alias '{}'",name)));
+            } else {
+                res.push("-x".into());
+                res.push(name);
+            },
+            None => res.push(a)
+        }
+    }
+    res
+}
+
+// the crates themselves, rather than their aliases (like 're' after 're=regex')
+pub fn unaliased(crates: &[String]) -> Vec<String> {
+    let aliases = get_aliases();
+    crates.iter().map(|c| aliases.get(c).unwrap_or(c).clone()).collect()
+}
+
 pub fn get_aliases() -> HashMap<String,String> {
     let alias_file = runner_directory().join("alias");
     if ! alias_file.is_file() { return HashMap::new(); }
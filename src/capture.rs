@@ -0,0 +1,26 @@
+// runner --capture runs the program with its output and errors captured, and then
+// prints a JSON report: how it exited, how long it took, and what it wrote. This is
+// for harnesses which run runner themselves, like graders or CI jobs checking examples.
+// runner's own messages still go to stderr, so stdout is only the report.
+use es::traits::*;
+use std::process::{Command,Stdio};
+use std::time::Instant;
+
+use crate::platform;
+
+// returns the program's exit code
+pub fn run(builder: &mut Command) -> i32 {
+    builder.stdin(Stdio::inherit()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    let start = Instant::now();
+    let output = builder.output().or_die("can't run program");
+    let duration = start.elapsed();
+    let code = platform::exit_code(&output.status);
+    let mut doc = json::JsonValue::new_object();
+    doc["status"] = code.into();
+    doc["success"] = output.status.success().into();
+    doc["duration"] = duration.as_secs_f64().into();
+    doc["stdout"] = String::from_utf8_lossy(&output.stdout).to_string().into();
+    doc["stderr"] = String::from_utf8_lossy(&output.stderr).to_string().into();
+    println!("{}",doc.dump());
+    code
+}
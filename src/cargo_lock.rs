@@ -1,8 +1,13 @@
 use toml;
 use std::fs;
+use std::collections::{BTreeMap,HashSet};
 use es::traits::*;
 use std::path::Path;
 
+use crate::cache::STATIC_CACHE;
+use crate::crate_utils;
+use crate::meta::Meta;
+
 #[derive(Deserialize)]
 pub struct CargoLock {
     pub package: Vec<Package>
@@ -21,3 +26,142 @@ pub fn read_cargo_lock(path: &Path) -> CargoLock {
     let body = fs::read_to_string(&lockf).or_die("cannot read Cargo.lock");
     toml::from_str(&body).or_die("can't deserialize")
 }
+
+impl CargoLock {
+    // dependencies are given as 'name', or as 'name version' (and maybe the
+    // source) when there is more than one version
+    pub fn find(&self, dep: &str) -> Option<&Package> {
+        let mut parts = dep.split_whitespace();
+        let name = parts.next()?;
+        let version = parts.next();
+        self.package.iter()
+            .find(|p| p.name == name && version.is_none_or(|v| p.version == v))
+    }
+
+    fn dependents(&self, p: &Package) -> Vec<&Package> {
+        self.package.iter()
+            .filter(|d| d.dependencies.iter().flatten()
+                .any(|dep| self.find(dep).is_some_and(|found| std::ptr::eq(found,p))))
+            .collect()
+    }
+}
+
+// 0.7 and 0.8 are as incompatible as 1.0 and 2.0
+fn major_version(version: &str) -> String {
+    let mut parts = version.split('.');
+    let major = parts.next().unwrap_or_default();
+    if major == "0" {
+        format!("0.{}",parts.next().unwrap_or_default())
+    } else {
+        major.to_string()
+    }
+}
+
+// the features which asking for these features brings in, using the crate's [features]
+fn implied_features(features: &toml::value::Table, wanted: Vec<String>) -> HashSet<String> {
+    let mut res = HashSet::new();
+    let mut todo = wanted;
+    while let Some(f) = todo.pop() {
+        if ! res.insert(f.clone()) {
+            continue;
+        }
+        for implied in features.get(&f).and_then(|v| v.as_array()).into_iter().flatten() {
+            let implied = implied.as_str().unwrap_or_default();
+            // 'dep:x' enables a dependency, 'x/feat' a dependency's feature (and x too, unless it's 'x?/feat')
+            if implied.starts_with("dep:") || implied.contains("?/") {
+                continue;
+            }
+            todo.push(implied.split('/').next().unwrap_or_default().to_string());
+        }
+    }
+    res
+}
+
+// features a crate in the static cache was built with which its line in Cargo.toml
+// doesn't ask for, because other crates in the cache need them
+fn unasked_features(name: &str, dep: &toml::Value, meta: &Meta) -> Vec<String> {
+    let e = match meta.get_meta_entry(name) {
+        Some(e) => e,
+        None => return Vec::new()
+    };
+    let crate_features = e.path.parent()
+        .and_then(|dir| crate_utils::cargo_dir(dir).ok())
+        .and_then(|(_,cargo_toml)| fs::read_to_string(cargo_toml).ok())
+        .and_then(|body| body.parse::<toml::Value>().ok())
+        .and_then(|toml| toml.get("features").and_then(|f| f.as_table()).cloned())
+        .unwrap_or_default();
+    let mut wanted: Vec<String> = dep.get("features").and_then(|f| f.as_array()).into_iter().flatten()
+        .filter_map(|f| f.as_str().map(|f| f.to_string()))
+        .collect();
+    let no_default = [dep.get("default-features"),dep.get("default_features")].iter()
+        .any(|d| d.and_then(|d| d.as_bool()) == Some(false));
+    if ! no_default {
+        wanted.push("default".into());
+    }
+    let asked = implied_features(&crate_features, wanted);
+    let mut res: Vec<String> = e.features.split_whitespace()
+        .filter(|f| ! asked.contains(*f))
+        .map(|f| f.to_string())
+        .collect();
+    res.sort();
+    res
+}
+
+// after a build, point out crates which are there in more than one major version,
+// and crates with features nobody asked the static cache for
+pub fn check(cache: &Path, meta: &Meta) {
+    let lock = read_cargo_lock(cache);
+    let mut versions: BTreeMap<&str,Vec<&str>> = BTreeMap::new();
+    for p in &lock.package {
+        versions.entry(&p.name).or_default().push(&p.version);
+    }
+    for (name,versions) in versions {
+        let majors: HashSet<_> = versions.iter().map(|v| major_version(v)).collect();
+        if majors.len() > 1 {
+            info!("{} is in the static cache as {} ('runner --why {}' says why)",name,versions.join(" and "),name);
+        }
+    }
+    let toml = fs::read_to_string(cache.join("Cargo.toml")).ok()
+        .and_then(|body| body.parse::<toml::Value>().ok());
+    let deps = toml.as_ref()
+        .and_then(|t| t.get("dependencies")).and_then(|d| d.as_table());
+    for (key,dep) in deps.into_iter().flatten() {
+        let name = dep.get("package").and_then(|p| p.as_str()).unwrap_or(key);
+        let unasked = unasked_features(name, dep, meta);
+        if ! unasked.is_empty() {
+            info!("{} also has the feature{} {}, which other crates need ('runner --why {}' lists them)",
+                name,if unasked.len() == 1 {""} else {"s"},unasked.join(", "),name);
+        }
+    }
+}
+
+fn print_dependents(lock: &CargoLock, p: &Package, indent: usize, seen: &mut HashSet<(String,String)>) {
+    let indents = "\t".repeat(indent);
+    if p.source.is_none() && p.name == STATIC_CACHE {
+        println!("{}the static cache (its Cargo.toml)",indents);
+        return;
+    }
+    if ! seen.insert((p.name.clone(),p.version.clone())) {
+        println!("{}{} {} (see above)",indents,p.name,p.version);
+        return;
+    }
+    println!("{}{} {}",indents,p.name,p.version);
+    for d in lock.dependents(p) {
+        print_dependents(lock, d, indent + 1, seen);
+    }
+}
+
+// runner --why: how a crate got into the static cache, as a tree of the crates
+// which depend on it, going back to the static cache's own Cargo.toml
+pub fn why(cache: &Path, name: &str) {
+    let lock = read_cargo_lock(cache);
+    let dashed = name.replace('_',"-");
+    let found: Vec<_> = lock.package.iter().filter(|p| p.name == name || p.name == dashed).collect();
+    if found.is_empty() {
+        es::quit(&format!("{} is not in the static cache",name));
+    }
+    let mut seen = HashSet::new();
+    for p in found {
+        print_dependents(&lock, p, 0, &mut seen);
+    }
+}
@@ -0,0 +1,121 @@
+// runner --compare a.rs b.rs builds both programs optimized and statically, and
+// runs them in turn (a b, then b a, and so on, so neither gets the warmer machine)
+// --repeat times. The result is how many times faster one is, with a 95%
+// confidence interval, so small differences aren't taken too seriously.
+use es::traits::*;
+use std::env;
+use std::env::consts::EXE_SUFFIX;
+use std::fs;
+use std::path::{Path,PathBuf};
+use std::process::{self,Stdio};
+use std::time::Instant;
+
+use crate::cache;
+use crate::crate_utils;
+use crate::timings::millis;
+
+// Student's t for a two-sided 95% interval, by degrees of freedom
+const T_95: [f64; 30] = [
+    12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228,
+    2.201, 2.179, 2.160, 2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086,
+    2.080, 2.074, 2.069, 2.064, 2.060, 2.056, 2.052, 2.048, 2.045, 2.042
+];
+
+fn t_95(df: usize) -> f64 {
+    if df == 0 {
+        f64::INFINITY
+    } else if df <= T_95.len() {
+        T_95[df-1]
+    } else {
+        1.96
+    }
+}
+
+struct Stats {
+    mean: f64,
+    stddev: f64,
+    min: f64,
+    n: usize,
+}
+
+impl Stats {
+    fn new(times: &[f64]) -> Stats {
+        let n = times.len();
+        let mean = times.iter().sum::<f64>() / n as f64;
+        // the sample standard deviation
+        let var = times.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / (n.max(2) - 1) as f64;
+        let min = times.iter().cloned().fold(f64::INFINITY, f64::min);
+        Stats { mean, stddev: var.sqrt(), min, n }
+    }
+
+    // the relative standard error of the mean
+    fn rel_error(&self) -> f64 {
+        self.stddev / self.mean / (self.n as f64).sqrt()
+    }
+}
+
+fn build(flags: &[String], file: &str, program: &Path) {
+    let mut builder = process::Command::new(env::current_exe().or_die("cannot find runner"));
+    builder.args(flags)
+        .args(["--static","--optimize","--compile-only","--output"]).arg(program)
+        .arg(file);
+    let output = builder.output().or_die("cannot run runner");
+    if ! output.status.success() {
+        eprint!("{}",String::from_utf8_lossy(&output.stderr));
+        es::quit(&format!("{} failed to build",file));
+    }
+}
+
+fn time(program: &Path, name: &str, passthrough: &[String]) -> f64 {
+    let start = Instant::now();
+    let status = process::Command::new(program).args(passthrough)
+        .stdout(Stdio::null())
+        .status().or_die("can't run program");
+    let elapsed = start.elapsed().as_secs_f64();
+    if ! status.success() {
+        es::quit(&format!("{} failed with {}",name,status));
+    }
+    elapsed
+}
+
+// builds both programs, runs them in turn and reports which is faster, if either
+pub fn compare(flags: Vec<String>, files: &[String], passthrough: Vec<String>, runs: usize, warmup: usize) {
+    let dir = cache::runner_directory().join("compare");
+    fs::create_dir_all(&dir).or_die("cannot create compare directory");
+    let programs: Vec<PathBuf> = files.iter().enumerate().map(|(i,file)| {
+        let name = crate_utils::path_file_name(&Path::new(file).with_extension(""));
+        let program = dir.join(format!("{}-{}{}",i+1,name,EXE_SUFFIX));
+        info!("building {}",file);
+        build(&flags, file, &program);
+        program
+    }).collect();
+
+    info!("running each {} times{}",runs,if warmup > 0 {format!(" after {} warmup runs",warmup)} else {String::new()});
+    let mut times = [Vec::new(),Vec::new()];
+    for round in 0..warmup+runs {
+        let order = if round % 2 == 0 {[0,1]} else {[1,0]};
+        for i in order {
+            let t = time(&programs[i], &files[i], &passthrough);
+            if round >= warmup {
+                times[i].push(t);
+            }
+        }
+    }
+    let stats: Vec<Stats> = times.iter().map(|t| Stats::new(t)).collect();
+    let width = files.iter().map(|f| f.len()).max().unwrap_or(0).max(4);
+    println!("{:<width$} {:>12} {:>12} {:>12}","","min","mean","stddev",width=width);
+    for (file,s) in files.iter().zip(&stats) {
+        println!("{:<width$} {:>12} {:>12} {:>12}",file,millis(s.min),millis(s.mean),millis(s.stddev),width=width);
+    }
+
+    // the ratio of the means, with its error from both
+    let (fast,slow) = if stats[0].mean <= stats[1].mean {(0,1)} else {(1,0)};
+    let ratio = stats[slow].mean / stats[fast].mean;
+    let t = t_95(runs.saturating_sub(1));
+    let interval = ratio * t * (stats[0].rel_error().powi(2) + stats[1].rel_error().powi(2)).sqrt();
+    if ratio - interval > 1.0 {
+        println!("{} is {:.2} ± {:.2} times faster than {} (95% confidence)",files[fast],ratio,interval,files[slow]);
+    } else {
+        println!("no clear difference: {} is {:.2} ± {:.2} times faster than {} (95% confidence)",files[fast],ratio,interval,files[slow]);
+    }
+}
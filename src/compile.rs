@@ -3,69 +3,159 @@ use lapp;
 use es::traits::*;
 use crate::crate_utils;
 use crate::cache;
+use crate::platform;
+use crate::logging;
+use crate::diagnostics::{self,SourceMap,LineMap};
 use crate::state::State;
+use crate::config::CONFIG;
 
 use std::process;
+use std::env;
+use std::fs;
+use std::io::{self,Write};
+use std::sync::Mutex;
+use std::time::SystemTime;
 use std::path::{Path,PathBuf};
 use std::env::consts::{DLL_SUFFIX,DLL_PREFIX};
 use std::collections::{HashSet};
-
-fn simplify_qualified_names(text: &str) -> String {
-    let std = "std::";
-    let mut res = String::new();
-    let mut s = text;
-    while let Some(pos) = s.find(std) {
-        res.push_str(&s[0..pos]);
-        s = &s[pos+std.len()..];
-        if let Some(pos) = s.find("::") {
-            s = &s[pos+2..];
-        }
-    }
-    res.push_str(s);
-    res
-}
+use crate::strutil::{self,after,word_after,split};
 
 // handle two useful cases:
 // - compile a crate as a dynamic library, given a name and an output dir
 // - compile a program, given a program
 pub fn compile_crate(args: &lapp::Args, state: &State,
     crate_name: &str, crate_path: &Path,
-    output_program: Option<&Path>, mut extern_crates: Vec<String>, features: Vec<String>) -> bool
+    output_program: Option<&Path>, extern_crates: Vec<String>, features: Vec<String>) -> bool
 {
-    let verbose = args.get_bool("verbose");
-    let simplify = ! args.get_bool("no-simplify");
-    let debug = ! state.optimize;
+    let builder = rustc_command(args, state, crate_name, crate_path, output_program, extern_crates, features);
+    let ok = run_rustc(args, builder, None);
+    if ok {
+        match output_program {
+            Some(program) => platform::codesign(program),
+            None => platform::codesign(&cache::get_cache(state).join(
+                format!("{}{}{}",DLL_PREFIX,crate_utils::proper_crate_name(crate_name),DLL_SUFFIX)))
+        }
+    }
+    // remember which rustc built the dynamic cache, for --info
+    if ok && ! state.exe && ! logging::dry_run() {
+        let version = es::shell("rustc --version");
+        fs::write(cache::dynamic_cache_dir().join(cache::RUSTC_VERSION),version).or_die("cannot write to dynamic cache");
+        cache::set_dynamic_edition(&crate_utils::proper_crate_name(crate_name), &state.edition);
+        cache::set_dynamic_host();
+    }
+    ok
+}
 
-    // implicit linking works fine, until it doesn't
-    extern_crates.extend(args.get_strings("extern"));
-    extern_crates.sort();
-    extern_crates.dedup();
-    // libc is such a special case
-    if args.get_bool("libc") {
-        extern_crates.push("libc".into());
+// a local crate from a '//: dep mylib=../mylib' header line. It goes into the dynamic
+// cache like any other crate, or for static builds is an rlib in local-deps.
+// Only rebuilt when the source has changed. Returns the crate name and library
+pub fn local_dependency(args: &lapp::Args, state: &State, name: Option<&str>, dir: &Path) -> (String,PathBuf) {
+    let cargo_toml = dir.join("Cargo.toml");
+    if ! cargo_toml.exists() {
+        args.quit(&format!("dep: no Cargo.toml in {}",dir.display()));
     }
+    let ci = crate_utils::crate_info(&cargo_toml);
+    let name = crate_utils::proper_crate_name(name.unwrap_or(&ci.name));
+    let source = dir.join("src").join("lib.rs");
+    let lib = if state.build_static {
+        local_deps_dir(state).join(format!("lib{}.rlib",name))
+    } else {
+        cache::get_cache(state).join(format!("{}{}{}",DLL_PREFIX,name,DLL_SUFFIX))
+    };
+    if is_newer(&lib, &dir.join("src")) {
+        return (name,lib);
+    }
+    info!("building local crate '{}' at {}",name,dir.display());
+    let ok = if state.build_static {
+        let out_dir = local_deps_dir(state);
+        if ! logging::dry_run() {
+            fs::create_dir_all(&out_dir).or_die("cannot create local-deps directory");
+        }
+        let mut builder = rustc();
+        builder.args(&["--edition",&ci.edition,"--crate-type","rlib","--crate-name",&name])
+            .arg("--out-dir").arg(&out_dir);
+        if state.optimize {
+            builder.arg("-O");
+        }
+        builder.arg(&source);
+        run_rustc(args, builder, None)
+    } else {
+        compile_crate(args, &State::dll(state.optimize,&ci.edition), &name, &source, None, Vec::new(), Vec::new())
+    };
+    if ! ok {
+        args.quit(&format!("cannot build local crate '{}'",name));
+    }
+    (name,lib)
+}
+
+fn local_deps_dir(state: &State) -> PathBuf {
+    cache::runner_directory().join("local-deps").join(if state.optimize {"release"} else {"debug"})
+}
+
+// is the file newer than everything in the directory?
+fn is_newer(file: &Path, dir: &Path) -> bool {
+    fn newest(dir: &Path) -> Option<SystemTime> {
+        let mut res = None;
+        for entry in fs::read_dir(dir).ok()?.flatten() {
+            let path = entry.path();
+            let time = if path.is_dir() {
+                newest(&path)
+            } else {
+                entry.metadata().and_then(|m| m.modified()).ok()
+            };
+            res = res.max(time);
+        }
+        res
+    }
+    match fs::metadata(file).and_then(|m| m.modified()) {
+        Ok(time) => newest(dir).is_none_or(|t| t <= time),
+        Err(_) => false
+    }
+}
+
+// RUSTC_WRAPPER (e.g. sccache) is honoured, as cargo does
+fn rustc() -> process::Command {
+    match env::var("RUSTC_WRAPPER") {
+        Ok(wrapper) if ! wrapper.is_empty() => {
+            let mut builder = process::Command::new(wrapper);
+            builder.arg("rustc");
+            builder
+        },
+        _ => process::Command::new("rustc")
+    }
+}
+
+// the rustc command line for compile_crate
+pub fn rustc_command(args: &lapp::Args, state: &State,
+    crate_name: &str, crate_path: &Path,
+    output_program: Option<&Path>, extern_crates: Vec<String>, features: Vec<String>) -> process::Command
+{
     let mut cfg = args.get_strings("cfg");
     let explicit_features = args.get_strings("features");
     for f in if explicit_features.len() > 0 {explicit_features} else {features} {
         cfg.push(format!("feature=\"{}\"",f));
     }
     let cache = cache::get_cache(&state);
-    let mut builder = process::Command::new("rustc");
+    let mut builder = rustc();
     if state.edition != "2015" {
         builder.args(&["--edition",&state.edition]);
     }
     if ! state.build_static { // stripped-down dynamic link
-        builder.args(&["-C","prefer-dynamic"]).args(&["-C","debuginfo=0"]);
+        builder.args(&["-C","prefer-dynamic"]);
         if let Ok(link) = args.get_string_result("link") {
-            if verbose { println!("linking against {}",link); }
+            verbose!("linking against {}",link);
             builder.arg("-L").arg(&link);
         }
-    } else { // static build
-        builder.arg(if state.optimize {"-O"} else {"-g"});
-        if state.optimize {
-            // no point in carrying around all that baggage...
-            builder.args(&["-C","debuginfo=0"]);
-        }
+    } else if state.optimize && state.opt_level.is_none() {
+        // an explicit --opt-level can't go with -O
+        builder.arg("-O");
+    }
+    // including debuginfo, which optimized and dynamic builds leave out by default
+    for opt in state.codegen_options() {
+        builder.arg("-C").arg(&opt);
+    }
+    for z in &state.unstable {
+        builder.arg(format!("-Z{}",z));
     }
     // implicitly linking against crates in the dynamic or static cache
     builder.arg("-L").arg(&cache);
@@ -75,11 +165,44 @@ pub fn compile_crate(args: &lapp::Args, state: &State,
         .arg("--crate-name").arg(&crate_utils::proper_crate_name(crate_name));
     } else {
         builder.arg("-o").arg(output_program.unwrap());
+        if state.rpath && ! state.build_static {
+            // rather than the library path at run time, which macOS may strip
+            for dir in [Path::new(&*crate_utils::RUSTUP_LIB), cache.as_path()] {
+                builder.arg("-C").arg(format!("link-arg=-Wl,-rpath,{}",dir.display()));
+            }
+        }
     }
     for c in cfg {
         builder.arg("--cfg").arg(&c);
     }
 
+    let externs = if ! state.build_static && state.exe {
+        cached_link_plan(args, state, &cache, extern_crates)
+    } else {
+        link_plan(args, state, &cache, extern_crates)
+    };
+    for ext in externs {
+        verbose!("extern {}",ext);
+        builder.arg("--extern").arg(&ext);
+    }
+    builder.arg(crate_path);
+    builder
+}
+
+// the --extern arguments for the crates, like 'regex=/path/to/libregex.so'
+fn link_plan(args: &lapp::Args, state: &State, cache: &Path, mut extern_crates: Vec<String>) -> Vec<String> {
+    let debug = ! state.optimize;
+
+    // implicit linking works fine, until it doesn't
+    extern_crates.extend(args.get_strings("extern"));
+    let mut extern_crates = cache::unaliased(&extern_crates);
+    extern_crates.sort();
+    extern_crates.dedup();
+    // libc is such a special case
+    if args.get_bool("libc") {
+        extern_crates.push("libc".into());
+    }
+
     // explicit --extern references require special treatment for
     // static builds, since the libnames include a hash.
     // So we look for the latest crate of this name
@@ -89,32 +212,123 @@ pub fn compile_crate(args: &lapp::Args, state: &State,
         let m = cache::get_metadata();
         extern_crates.into_iter().map(|c|
             (m.get_full_crate_name(&c,debug)
-                .or_then_die(|_| format!("no such crate '{}' in static cache: use --add",c)),c)
+                .or_then_die(|_| format!("no such crate '{}' in static cache: use --add or --auto-add",c)),c)
         ).collect()
     } else {
-        extern_crates.into_iter().map(|c|
-            (format!("{}{}{}",DLL_PREFIX,c,DLL_SUFFIX),c)
-        ).collect()
+        if ! state.build_static {
+            cache::check_dynamic_host();
+        }
+        extern_crates.into_iter().map(|c| {
+            let dll = format!("{}{}{}",DLL_PREFIX,c,DLL_SUFFIX);
+            // a DLL without the import library this toolchain needs was built with the other ABI
+            if let Some(import_lib) = crate_utils::import_library(&c) {
+                if cache.join(&dll).exists() && ! cache.join(&import_lib).exists() {
+                    es::quit(&format!("{} in the dynamic cache has no {}, so was not built with a {} toolchain: rebuild it with -C",
+                        dll,import_lib,crate_utils::windows_abi().unwrap_or_default()));
+                }
+            }
+            (dll,c)
+        }).collect()
     };
+    extern_crates.into_iter()
+        .map(|(name,c)| format!("{}={}",c,cache.join(&name).display()))
+        .collect()
+}
 
-    for (name,c) in extern_crates {
-        let full_path = PathBuf::from(&cache).join(&name);
-        let ext = format!("{}={}",c,full_path.display());
-        if verbose {
-            println!("extern {}",ext);
+// dynamic builds of a snippet link the same way run after run, so the plan
+// is kept until the dynamic cache (or the aliases) change
+fn cached_link_plan(args: &lapp::Args, state: &State, cache: &Path, extern_crates: Vec<String>) -> Vec<String> {
+    let mut key = extern_crates.clone();
+    key.extend(args.get_strings("extern"));
+    key.push(format!("libc={} {}",args.get_bool("libc"),state.describe()));
+    let file = cache::link_plan_file(&key.join(" "));
+    if let Ok(plan) = fs::read_to_string(&file) {
+        trace!("link plan {}",file.display());
+        return plan.lines().map(|l| l.to_string()).collect();
+    }
+    let plan = link_plan(args, state, cache, extern_crates);
+    if ! logging::dry_run() {
+        let dir = file.parent().unwrap();
+        if let Err(e) = fs::create_dir_all(dir).and_then(|_| fs::write(&file, plan.join("\n"))) {
+            verbose!("cannot keep link plan {}: {}",file.display(),e);
         }
-        builder.arg("--extern").arg(&ext);
     }
-    builder.arg(crate_path);
-    if simplify {
-        if isatty::stderr_isatty() {
-            builder.args(&["--color","always"]);
+    plan
+}
+
+// --simplify wins over a --no-simplify from config.toml
+fn simplifying(args: &lapp::Args) -> bool {
+    args.get_bool("simplify") || ! args.get_bool("no-simplify")
+}
+
+// rustc's errors when it last failed, so runner can see what was missing
+static ERRORS: Mutex<String> = Mutex::new(String::new());
+
+pub fn last_errors() -> String {
+    ERRORS.lock().unwrap().clone()
+}
+
+fn keep_errors(ok: bool, err: &str) {
+    if ! ok {
+        *ERRORS.lock().unwrap() = err.to_string();
+    }
+}
+
+// run rustc, showing its errors as asked for. With a source map,
+// JSON diagnostics refer to the user's snippet, not the generated program
+pub fn run_rustc(args: &lapp::Args, mut builder: process::Command, source_map: Option<&SourceMap>) -> bool {
+    let simplify = simplifying(args);
+    let color = diagnostics::use_color(&args.get_string("color")).unwrap_or_else(|e| args.quit(&e));
+    let message_format = args.get_string("message-format");
+    if message_format != "human" && message_format != "json" {
+        args.quit(&format!("--message-format must be human or json, not '{}'",message_format));
+    }
+    let error_format = args.get_string("error-format");
+    if error_format != "human" && error_format != "short" {
+        args.quit(&format!("--error-format must be human or short, not '{}'",error_format));
+    }
+    if message_format == "json" || error_format == "short" {
+        // either way, we need rustc's JSON to find the snippet lines
+        builder.arg("--error-format=json");
+        trace!("{:?}",builder);
+        if logging::dry_run_command(&builder) {
+            return true;
         }
         let output = builder.output().or_die("can't run rustc");
+        // some -Z options, like print-type-sizes, report on stdout
+        io::stdout().write_all(&output.stdout).or_die("i/o?");
+        let err = String::from_utf8_lossy(&output.stderr);
+        if message_format == "json" {
+            diagnostics::print_json(&err, source_map);
+        } else {
+            diagnostics::print_short(&err, source_map);
+        }
+        keep_errors(output.status.success(), &err);
+        return output.status.success();
+    }
+    // rustc can't tell if it's writing to a terminal when we capture its output
+    builder.args(["--color", if color {"always"} else {"never"}]);
+    trace!("{:?}",builder);
+    if logging::dry_run_command(&builder) {
+        return true;
+    }
+    let explain = args.get_bool("explain");
+    if simplify || explain {
+        let output = builder.output().or_die("can't run rustc");
+        io::stdout().write_all(&output.stdout).or_die("i/o?");
         let status = output.status.success();
-        if ! status {
-            let err = String::from_utf8_lossy(&output.stderr);
-            eprintln!("{}",simplify_qualified_names(&err));
+        let err = String::from_utf8_lossy(&output.stderr);
+        keep_errors(status, &err);
+        if ! simplify {
+            eprint!("{}",err);
+        } else if ! status {
+            let err = diagnostics::simplify_qualified_names(&err);
+            eprint!("{}",CONFIG.errors.apply(&err));
+        }
+        if explain && ! status {
+            if let Some(code) = diagnostics::first_error_code(&err) {
+                explain_error(&code);
+            }
         }
         status
     } else {
@@ -122,38 +336,349 @@ pub fn compile_crate(args: &lapp::Args, state: &State,
     }
 }
 
-pub fn massage_snippet(code: String, prelude: String,
-        extern_crates: Vec<String>, wild_crates: Vec<String>, macro_crates: HashSet<String>, body_prelude: String, is2018: bool) -> (String,Vec<String>) {
-    use crate::strutil::{after,word_after,split};
+// --type: the expression is compiled (but not run) as 'let _: () = EXPR;'
+// and rustc complains about the type it really has
+pub fn print_type(args: &lapp::Args, mut builder: process::Command) -> bool {
+    builder.arg("--error-format=json");
+    trace!("{:?}",builder);
+    if logging::dry_run_command(&builder) {
+        return true;
+    }
+    let output = builder.output().or_die("can't run rustc");
+    if output.status.success() {
+        println!("()");
+        return true;
+    }
+    let err = String::from_utf8_lossy(&output.stderr);
+    let mut errors = String::new();
+    for line in err.lines() {
+        if let Ok(doc) = json::parse(line) {
+            if let Some(ty) = diagnostics::found_type(&doc) {
+                if ! simplifying(args) {
+                    println!("{}",ty);
+                } else {
+                    print!("{}",diagnostics::simplify_qualified_names(&ty));
+                }
+                return true;
+            }
+            if doc["level"] == "error" {
+                errors += doc["rendered"].as_str().unwrap_or_default();
+            }
+        }
+    }
+    // the expression didn't compile for other reasons
+    eprint!("{}",errors);
+    false
+}
+
+// rustc pages the explanation itself if stdout is a terminal
+fn explain_error(code: &str) {
+    trace!("rustc --explain {}",code);
+    process::Command::new("rustc").args(["--explain",code])
+        .status().or_die("can't run rustc");
+}
+
+// the prelude may have sections which are only wanted when a crate is used:
+//    //#if crate(regex)
+//    use regex::Regex;
+//    //#endif
+// (or all on one line, '//#if crate(regex) use regex::Regex; //#endif')
+fn prelude_for_crates(prelude: &str, crates: &HashSet<String>) -> String {
+    let mut res = String::new();
+    let mut keep = true;
+    for line in prelude.lines() {
+        let trimmed = line.trim();
+        if let Some(rest) = trimmed.strip_prefix("//#if crate(") {
+            let (name,rest) = split(rest,')');
+            let wanted = crates.contains(&crate_utils::proper_crate_name(name.trim()));
+            let rest = rest.trim();
+            if let Some(idx) = rest.find("//#endif") {
+                if wanted {
+                    res += rest[0..idx].trim();
+                    res.push('\n');
+                }
+            } else {
+                keep = wanted;
+            }
+        } else if trimmed.starts_with("//#endif") {
+            keep = true;
+        } else if keep {
+            res += line;
+            res.push('\n');
+        }
+    }
+    res
+}
+
+// a snippet is compiled from the runner bin directory, so 'mod helper;' needs
+// to be told that it means helper.rs (or helper/mod.rs) next to the snippet
+pub fn local_modules(code: &str, dir: &Path) -> String {
+    let mut res = String::new();
+    for line in code.lines() {
+        let trimmed = line.trim();
+        let file = module_name(trimmed).and_then(|name| {
+            [dir.join(name).with_extension("rs"), dir.join(name).join("mod.rs")]
+                .iter().find(|p| p.is_file()).cloned()
+        });
+        if let Some(file) = file {
+            let file = file.canonicalize().unwrap_or(file);
+            res += &format!("#[path={:?}] {}",file.display().to_string(),trimmed);
+        } else {
+            res += line;
+        }
+        res.push('\n');
+    }
+    res
+}
+
+// 'helper' from 'mod helper;', after local_modules
+pub fn module_name(line: &str) -> Option<&str> {
+    let line = line.strip_prefix("#[path=").and_then(|l| l.split_once("] ")).map(|(_,l)| l).unwrap_or(line);
+    let line = line.strip_prefix("pub ").unwrap_or(line);
+    line.strip_prefix("mod ")?.strip_suffix(';').map(str::trim)
+}
+
+// items which can go outside run(), perhaps after 'pub', 'async' or 'unsafe'
+const ITEMS: &[&str] = &["fn ","struct ","enum ","trait ","impl ","impl<","type ","const ","static ",
+    "macro_rules!","use ","extern crate ","mod "];
+
+// the item after any 'pub', 'async' or 'unsafe'
+fn item_start(line: &str) -> &str {
+    let mut line = line;
+    while let Some(rest) = ["pub(crate) ","pub(super) ","pub ","async ","unsafe "].iter().find_map(|p| line.strip_prefix(p)) {
+        line = rest;
+    }
+    line
+}
+
+// does an item start here? Only at the beginning of a line, as in most snippets
+fn is_item(line: &str) -> bool {
+    if line.starts_with(char::is_whitespace) {
+        return false;
+    }
+    let line = item_start(line);
+    ITEMS.iter().any(|item| line.starts_with(item))
+}
+
+// 'start' from 'pub fn start<T>(...'
+fn fn_name(line: &str) -> Option<&str> {
+    let rest = item_start(line.trim_start()).strip_prefix("fn ")?.trim_start();
+    let len = rest.find(|c: char| ! (c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+    Some(&rest[..len]).filter(|name| ! name.is_empty())
+}
+
+// the parameters of a function's signature, and what comes after them. They begin
+// at the first '(' outside the generics, as in 'fn start<F: Fn() -> i32>(f: F)'
+fn parameters(signature: &str) -> Option<(&str,&str)> {
+    let (mut angle, mut prev) = (0, ' ');
+    let open = signature.char_indices().find(|&(_,c)| {
+        match c {
+            '<' => angle += 1,
+            '>' if prev != '-' => angle -= 1,
+            _ => {}
+        }
+        prev = c;
+        c == '(' && angle == 0
+    })?.0;
+    let mut depth = 0;
+    let close = open + signature[open..].char_indices().find(|&(_,c)| {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            _ => {}
+        }
+        depth == 0
+    })?.0;
+    Some((signature[open+1..close].trim(), &signature[close+1..]))
+}
+
+// where the item starting at lines[start] ends (after any attributes and doc comments):
+// the line after it, once its brackets balance and it has had a block or a ';'.
+// The brackets are counted in the same lines with strings, characters and comments
+// blanked out by strutil::blank_literals. None if it never does
+fn item_end(lines: &[&str], code: &[&str], start: usize) -> Option<usize> {
+    let attribute = |j: usize| (code[j].starts_with("#[") && ! code[j].starts_with("#!["))
+        || (lines[j].starts_with("///") && code[j].trim().is_empty());
+    let k = start + (start..code.len()).take_while(|&j| attribute(j)).count();
+    if ! code.get(k).is_some_and(|l| is_item(l)) {
+        return None;
+    }
+    let (mut depth, mut done) = (0i32, false);
+    for (j,line) in code.iter().enumerate().skip(k) {
+        for c in line.chars() {
+            match c {
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' => {
+                    depth -= 1;
+                    if depth < 0 {
+                        return None;
+                    }
+                    done = done || (c == '}' && depth == 0);
+                },
+                ';' if depth == 0 => done = true,
+                _ => {}
+            }
+        }
+        if done && depth == 0 {
+            return Some(j + 1);
+        }
+    }
+    None
+}
+
+// the call of the snippet's entry point, which is the function after '#[runner::main]'
+// or the one named by '//: entry'. The attribute is blanked out, since rustc doesn't
+// know it. It gets the arguments if it takes any, and its error is reported like
+// any snippet's
+pub fn entry_call(code: &mut String, named: Option<String>) -> Option<String> {
+    // looked for with strings and comments blanked out, so that neither counts
+    let masked = strutil::blank_literals(code, true);
+    let lines: Vec<&str> = masked.lines().collect();
+    let mut name = named;
+    if let Some(i) = lines.iter().position(|l| l.trim() == "#[runner::main]") {
+        let mut code_lines: Vec<&str> = code.lines().collect();
+        code_lines[i] = "";
+        *code = code_lines.join("\n") + "\n";
+        let after = lines[i+1..].iter().find(|l| ! l.trim().is_empty() && ! l.trim_start().starts_with("#["))?;
+        name = Some(fn_name(after)?.to_string());
+    }
+    let name = name?;
+    let code_lines: Vec<&str> = code.lines().collect();
+    // going from item to item, so that a function's body is not searched
+    let mut k = 0;
+    while lines.get(k).is_some_and(|l| ! (is_item(l) && fn_name(l) == Some(name.as_str()))) {
+        k = if lines[k].starts_with("#[") {k + 1} else {item_end(&code_lines, &lines, k).unwrap_or(k + 1)};
+    }
+    if k == lines.len() {
+        es::quit(&format!("no function '{}' to be the entry point",name));
+    }
+    let rest = lines[k..].join("\n");
+    let signature = &rest[..rest.find('{').unwrap_or(rest.len())];
+    let (params,after) = parameters(signature)
+        .unwrap_or_else(|| es::quit(&format!("cannot make out the parameters of '{}'",name)));
+    let call = format!("{}({})",name,if params.is_empty() {""} else {"args"});
+    // only a Result (or io::Result, anyhow::Result...) has an error to report
+    let returns = after.trim_start().strip_prefix("->").unwrap_or("").trim_start();
+    let returns = returns.split(|c: char| c == '<' || c.is_whitespace()).next().unwrap_or("");
+    Some(if returns.rsplit("::").next() == Some("Result") {
+        format!("if let Err(e) = {} {{ println!(\"error: {{:?}}\",e); }}",call)
+    } else {
+        format!("{};",call)
+    })
+}
+
+// the generated program, with the snippet line (and column adjustment)
+// each line came from, so that rustc's errors can point back to the snippet
+#[derive(Default)]
+struct Generated {
+    lines: Vec<(String,Option<(usize,isize)>)>,
+}
+
+impl Generated {
+    fn push(&mut self, line: String, origin: Option<(usize,isize)>) {
+        self.lines.push((line,origin));
+    }
 
-    fn indent_line(line: &str) -> String {
-        format!("    {}\n",line)
+    fn push_text(&mut self, text: &str) {
+        for line in text.lines() {
+            self.push(line.into(), None);
+        }
+    }
+
+    fn append(&mut self, other: Generated) {
+        self.lines.extend(other.lines);
     }
+}
 
-    let mut prefix = prelude;
-    let mut crate_begin = String::new();
-    let mut body = String::new();
+// code written for earlier editions which 2024 rejects: 'gen' is now a keyword
+// (as in rand's 'rng.gen()'), and extern blocks and attributes like no_mangle must
+// be marked unsafe. The new forms are fine with any edition since Rust 1.82
+pub fn for_edition_2024(code: &str) -> String {
+    // only code is changed, and not strings or comments which mention these
+    let masked = strutil::blank_literals(code, true);
+    let mut res = String::new();
+    for (line,code) in code.lines().zip(masked.lines()) {
+        let (mut line, mut code) = (line.to_string(), code.to_string());
+        let mut replace = |from: &str, to: &str| {
+            let mut at = 0;
+            while let Some(idx) = code[at..].find(from).map(|i| at + i) {
+                line.replace_range(idx..idx+from.len(), to);
+                code.replace_range(idx..idx+from.len(), to);
+                at = idx + to.len();
+            }
+        };
+        replace(".gen(",".r#gen(");
+        replace(".gen::<",".r#gen::<");
+        replace("fn gen(","fn r#gen(");
+        replace("fn gen<","fn r#gen<");
+        for attr in ["no_mangle","export_name","link_section"] {
+            let start = format!("#[{}",attr);
+            if let Some(idx) = code.find(&start) {
+                if let Some(end) = code[idx..].find(']') {
+                    for text in [&mut line, &mut code] {
+                        text.insert(idx + end, ')');
+                        text.replace_range(idx..idx+2, "#[unsafe(");
+                    }
+                }
+            }
+        }
+        let trimmed = code.trim_start();
+        if trimmed.starts_with("extern ") && ! trimmed.starts_with("extern crate") && ! trimmed.contains("fn ") && trimmed.trim_end().ends_with('{') {
+            let ws = code.len() - trimmed.len();
+            line.insert_str(ws, "unsafe ");
+        }
+        res += &line;
+        res.push('\n');
+    }
+    res
+}
+
+pub fn massage_snippet(code: String, prelude: String,
+        extern_crates: Vec<String>, wild_crates: Vec<String>, macro_crates: HashSet<String>, body_prelude: String, is2018: bool)
+        -> (String,Vec<String>,LineMap) {
+    const INDENT: &str = "    ";
+    let indent = INDENT.len() as isize;
+
+    let mut prefix = Generated::default();
+    let mut crate_begin = Generated::default();
+    let mut body = Generated::default();
     let mut deduced_externs = Vec::new();
+    let mut modules = Vec::new();
 
-    body += &body_prelude;
+    let aliases = cache::get_aliases();
     if extern_crates.len() > 0 {
-        let aliases = cache::get_aliases();
         for c in &extern_crates {
-            prefix += &if let Some(aliased) = aliases.get(c) {
-                format!("extern crate {} as {};\n",aliased,c)
+            prefix.push(if let Some(aliased) = aliases.get(c) {
+                format!("extern crate {} as {};",aliased,c)
             } else {
                 let mac = if macro_crates.contains(c) {"#[macro_use] "} else {""};
-                format!("{}extern crate {};\n",mac,c)
-            };
+                format!("{}extern crate {};",mac,c)
+            }, None);
         }
         for c in wild_crates {
-            prefix += &format!("use {}::*;\n",c);
+            prefix.push(format!("use {}::*;",c), None);
         }
     }
-    let mut lines = code.lines();
+    let lines: Vec<&str> = code.lines().collect();
+    let mut i = 0;
     let mut first = true;
-    for line in lines.by_ref() {
-        let line = line.trim_start();
+    let deduce_use = |line: &str, deduced_externs: &mut Vec<String>| {
+        if is2018 {
+            if let Some(path) = word_after(line,"use ") {
+                let (name,rest) = split(&path,':');
+                if ! ["std","core","alloc","crate"].contains(&name) || rest == "" {
+                    deduced_externs.push(name.into());
+                }
+            }
+        }
+    };
+    while i < lines.len() {
+        let line = lines[i];
+        i += 1;
+        let lineno = i;
+        let trimmed = line.trim_start();
+        let ws = (line.len() - trimmed.len()) as isize;
+        let line = trimmed;
         if first { // files may start with #! shebang or comment...
             if line.starts_with("#!/") || line.starts_with("//") {
                 continue;
@@ -167,55 +692,95 @@ pub fn massage_snippet(code: String, prelude: String,
             if let Some(crate_name) = word_after(rest,"extern crate ") {
                 deduced_externs.push(crate_name);
             }
-            prefix += line;
-            prefix.push('\n');
+            prefix.push(line.into(), Some((lineno,ws)));
         } else
-        if line.starts_with("extern ") || line.starts_with("use ") {
+        if (line.starts_with("extern ") || line.starts_with("use ")) && line.trim_end().ends_with(';') {
             if let Some(crate_name) = word_after(line,"extern crate ") {
                 deduced_externs.push(crate_name);
             }
-            if is2018 {
-                if let Some(path) = word_after(line,"use ") {
-                    let (name,rest) = split(&path,':');
-                    if ! ["std","core","alloc","crate"].contains(&name) || rest == "" {
-                        deduced_externs.push(name.into());
-                    }
-                }
-            }
-            prefix += line;
-            prefix.push('\n');
+            deduce_use(line, &mut deduced_externs);
+            prefix.push(line.into(), Some((lineno,ws)));
+        } else if let Some(name) = module_name(line) {
+            // modules in other files can't go inside a function
+            modules.push(name.to_string());
+            prefix.push(line.into(), Some((lineno,ws)));
         } else
         if line.starts_with("#![") {
             // inner attributes really need to be at the top of the file
-            crate_begin += line;
-            crate_begin.push('\n');
+            crate_begin.push(line.into(), Some((lineno,ws)));
         } else
         if line.len() > 0 {
-            body += &indent_line(line);
+            // the body starts here
+            i -= 1;
             break;
         }
     }
-    // and indent the rest!
-    body.extend(lines.map(indent_line));
+    // the rest goes in run(), indented, except for items like functions and structs.
+    // They go before run(), so they are ordinary items and not nested in a function
+    let mut items = Generated::default();
+    // the body's own prelude comes first, with nothing in the snippet to point to
+    for line in body_prelude.lines() {
+        body.push(format!("{}{}",INDENT,line), None);
+    }
+    let masked = strutil::blank_literals(&code, true);
+    let code_lines: Vec<&str> = masked.lines().collect();
+    while i < lines.len() {
+        if let Some(end) = item_end(&lines, &code_lines, i) {
+            for (j,line) in lines.iter().enumerate().take(end).skip(i) {
+                if let Some(name) = module_name(line) {
+                    modules.push(name.to_string());
+                }
+                if let Some(crate_name) = word_after(line,"extern crate ") {
+                    deduced_externs.push(crate_name);
+                }
+                if line.starts_with("use ") {
+                    deduce_use(line, &mut deduced_externs);
+                }
+                items.push(line.to_string(), Some((j+1,0)));
+            }
+            i = end;
+            continue;
+        }
+        body.push(format!("{}{}",INDENT,lines[i]), Some((i+1,-indent)));
+        i += 1;
+    }
+    prefix.append(items);
 
+    // 'use helper::twice' is not about a crate if helper is a module
+    deduced_externs.retain(|c| ! modules.contains(c));
     deduced_externs.extend(extern_crates);
     deduced_externs.sort();
     deduced_externs.dedup();
 
-    let massaged_code = format!("{}
-{}
+    // conditional prelude sections may refer to a crate by its alias or real name
+    let used_crates: HashSet<String> = deduced_externs.iter()
+        .flat_map(|c| vec![Some(c.clone()), aliases.get(c).cloned()])
+        .flatten()
+        .map(|c| crate_utils::proper_crate_name(&c))
+        .collect();
 
-fn run(args: Vec<String>) -> std::result::Result<(),Box<dyn std::error::Error+Sync+Send>> {{
-{}    Ok(())
-}}
-fn main() {{
-    if let Err(e) = run(std::env::args().collect()) {{
-        println!(\"error: {{:?}}\",e);
-    }}
-}}
-",crate_begin,prefix,body);
+    let mut program = crate_begin;
+    program.push_text(&prelude_for_crates(&prelude, &used_crates));
+    program.append(prefix);
+    program.push_text("
+fn run(args: Vec<String>) -> std::result::Result<(),Box<dyn std::error::Error+Sync+Send>> {");
+    program.append(body);
+    program.push_text("    Ok(())
+}
+fn main() {
+    if let Err(e) = run(std::env::args().collect()) {
+        println!(\"error: {:?}\",e);
+    }
+}");
 
-    (massaged_code, deduced_externs)
+    let mut massaged_code = String::new();
+    let mut line_map = Vec::new();
+    for (line,origin) in program.lines {
+        massaged_code += &line;
+        massaged_code.push('\n');
+        line_map.push(origin);
+    }
+    (massaged_code, deduced_externs, line_map)
 
 }
 
@@ -0,0 +1,185 @@
+// runner --completions SHELL writes a completion script for bash, zsh, fish or
+// PowerShell. The flags come from the usage text, so they are always current;
+// crate names for -x, --doc, --update and -P come from 'runner --crate-names',
+// which reads the static cache's metadata when completing.
+use crate::cache;
+use crate::meta::Meta;
+
+// these take a crate name, either as their value or as the argument which follows
+const CRATE_FLAGS: &[&str] = &["extern","wild","macro","doc","update","crate-path"];
+
+struct Flag {
+    short: Option<String>,
+    long: String,
+    help: String,
+    takes_value: bool,
+    multiple: bool,
+}
+
+impl Flag {
+    fn takes_crate(&self) -> bool {
+        CRATE_FLAGS.contains(&self.long.as_str())
+    }
+
+    fn names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        if let Some(ref short) = self.short {
+            names.push(format!("-{}",short));
+        }
+        names.push(format!("--{}",self.long));
+        names
+    }
+}
+
+// lines like '  -x, --extern... (string) add an extern crate to the snippet'
+fn parse_flags(usage: &str) -> Vec<Flag> {
+    let mut flags = Vec::new();
+    for line in usage.lines().map(str::trim).filter(|l| l.starts_with('-')) {
+        let (short,rest) = match line.strip_prefix('-').and_then(|l| l.split_once(", ")) {
+            Some((s,rest)) if s.len() == 1 => (Some(s.to_string()),rest),
+            _ => (None,line)
+        };
+        let rest = rest.trim_start_matches('-');
+        let (name,rest) = rest.split_once(' ').unwrap_or((rest,""));
+        let mut rest = rest.trim();
+        let mut takes_value = false;
+        let mut multiple = name.ends_with("...");
+        if rest.starts_with('(') {
+            if let Some(end) = rest.find(')') {
+                let spec = &rest[1..end];
+                takes_value = true;
+                multiple = multiple || spec.ends_with("...");
+                rest = rest[end+1..].trim();
+            }
+        }
+        flags.push(Flag {
+            short,
+            long: name.trim_end_matches("...").to_string(),
+            help: rest.to_string(),
+            takes_value,
+            multiple,
+        });
+    }
+    flags
+}
+
+// the flags which take a value, like '-x' and '--extern'
+pub fn value_flags(usage: &str) -> Vec<String> {
+    parse_flags(usage).into_iter().filter(|f| f.takes_value).flat_map(|f| f.names()).collect()
+}
+
+// the crates in the static cache, and the aliases which can be used with -x
+pub fn crate_names() {
+    let mut names = Meta::crate_names(&cache::static_cache_dir());
+    names.extend(cache::get_aliases().into_keys());
+    names.sort();
+    names.dedup();
+    for name in names {
+        println!("{}",name);
+    }
+}
+
+fn bash(flags: &[Flag]) -> String {
+    let all: Vec<String> = flags.iter().flat_map(Flag::names).collect();
+    let crates: Vec<String> = flags.iter().filter(|f| f.takes_crate()).flat_map(Flag::names).collect();
+    format!(r#"# runner completion for bash: source this, or put it in ~/.local/share/bash-completion/completions/runner
+_runner() {{
+    local cur="${{COMP_WORDS[COMP_CWORD]}}"
+    local prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    case "$prev" in
+        {crates})
+            COMPREPLY=($(compgen -W "$(runner --crate-names 2>/dev/null)" -- "$cur"))
+            return;;
+    esac
+    if [[ "$cur" == -* ]]; then
+        COMPREPLY=($(compgen -W "{all}" -- "$cur"))
+    else
+        COMPREPLY=($(compgen -f -- "$cur"))
+    fi
+}}
+complete -o filenames -F _runner runner
+"#, crates=crates.join("|"), all=all.join(" "))
+}
+
+fn zsh(flags: &[Flag]) -> String {
+    let mut specs = String::new();
+    for f in flags {
+        let help = f.help.replace('\'',"'\\''").replace('[',"\\[").replace(']',"\\]").replace(':',"\\:");
+        let action = if f.takes_crate() {
+            // -x needs a crate, --doc and friends may have one
+            if f.takes_value {":crate:_runner_crates"} else {"::crate:_runner_crates"}
+        } else if f.takes_value {
+            ":value: "
+        } else {
+            ""
+        };
+        let repeat = if f.multiple {"*"} else {""};
+        for name in f.names() {
+            specs += &format!("    '{}{}[{}]{}' \\\n",repeat,name,help,action);
+        }
+    }
+    format!(r#"#compdef runner
+# runner completion for zsh: put this in a directory on $fpath as _runner
+_runner_crates() {{
+    local -a crates
+    crates=(${{(f)"$(runner --crate-names 2>/dev/null)"}})
+    _describe 'crate' crates
+}}
+_arguments -s \
+{specs}    '*:file:_files'
+"#, specs=specs)
+}
+
+fn fish(flags: &[Flag]) -> String {
+    let mut res = String::from("# runner completion for fish: put this in ~/.config/fish/completions/runner.fish\n");
+    for f in flags {
+        let mut line = String::from("complete -c runner");
+        if let Some(ref short) = f.short {
+            line += &format!(" -s {}",short);
+        }
+        line += &format!(" -l {}",f.long);
+        if f.takes_crate() {
+            line += " -x -a '(runner --crate-names 2>/dev/null)'";
+        } else if f.takes_value {
+            line += " -r";
+        }
+        line += &format!(" -d '{}'",f.help.replace('\\',"\\\\").replace('\'',"\\'"));
+        res += &line;
+        res.push('\n');
+    }
+    res
+}
+
+fn powershell(flags: &[Flag]) -> String {
+    let quoted = |names: Vec<String>| names.iter().map(|n| format!("'{}'",n)).collect::<Vec<_>>().join(",");
+    let all = quoted(flags.iter().flat_map(Flag::names).collect());
+    let crates = quoted(flags.iter().filter(|f| f.takes_crate()).flat_map(Flag::names).collect());
+    format!(r#"# runner completion for PowerShell: add this to your $PROFILE
+Register-ArgumentCompleter -Native -CommandName runner -ScriptBlock {{
+    param($wordToComplete, $commandAst, $cursorPosition)
+    $words = @($commandAst.CommandElements | ForEach-Object {{ $_.ToString() }})
+    $prev = if ($wordToComplete) {{ $words[-2] }} else {{ $words[-1] }}
+    if (@({crates}) -ccontains $prev) {{
+        runner --crate-names 2>$null | Where-Object {{ $_ -like "$wordToComplete*" }} | ForEach-Object {{
+            [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterValue', $_)
+        }}
+    }} elseif ($wordToComplete -like '-*') {{
+        @({all}) | Where-Object {{ $_ -clike "$wordToComplete*" }} | ForEach-Object {{
+            [System.Management.Automation.CompletionResult]::new($_, $_, 'ParameterName', $_)
+        }}
+    }}
+}}
+"#, crates=crates, all=all)
+}
+
+pub fn completions(shell: &str, usage: &str) {
+    let flags = parse_flags(usage);
+    let script = match shell {
+        "bash" => bash(&flags),
+        "zsh" => zsh(&flags),
+        "fish" => fish(&flags),
+        "powershell" | "pwsh" => powershell(&flags),
+        _ => es::quit(&format!("no completions for '{}': use bash, zsh, fish or powershell",shell))
+    };
+    print!("{}",script);
+}
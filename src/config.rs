@@ -0,0 +1,175 @@
+// persistent defaults, kept in config.toml in the runner directory:
+//
+//    edition = "2021"
+//    static = true
+//    optimize = false
+//    externs = ["regex", "serde_json"]
+//    editor = "nvim"
+//    simplify = true
+//    raw = true
+//    auto_add = true
+//    rpath = true
+//    registry = "internal"
+//    install_dir = "~/bin"
+//
+//    [errors]
+//    max = 3
+//
+//    [imports]
+//    itertools = "wild"     # -x itertools means -X itertools
+//    lazy_static = "macro"  # and -M
+//
+// These become default runner flags, which the command line overrides.
+use es::traits::*;
+use std::fs;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use toml::Value;
+
+use crate::cache;
+use crate::diagnostics::ErrorRules;
+
+lazy_static! {
+    pub static ref CONFIG: Config = Config::read(&config_file());
+}
+
+pub fn config_file() -> PathBuf {
+    cache::runner_directory().join("config.toml")
+}
+
+#[derive(Default)]
+pub struct Config {
+    pub edition: Option<String>,
+    pub build_static: Option<bool>,
+    pub optimize: Option<bool>,
+    pub externs: Vec<String>,
+    pub editor: Option<String>,
+    pub simplify: Option<bool>,
+    pub raw: Option<bool>,
+    pub auto_add: Option<bool>,
+    pub rpath: Option<bool>,
+    pub registry: Option<String>,
+    pub install_dir: Option<String>,
+    pub errors: ErrorRules,
+    // crate name to "wild" or "macro"
+    pub imports: HashMap<String,String>,
+}
+
+fn bad_value(key: &str, expected: &str) -> ! {
+    es::quit(&format!("config.toml: '{}' should be {}",key,expected));
+}
+
+fn as_bool(key: &str, v: &Value) -> bool {
+    v.as_bool().unwrap_or_else(|| bad_value(key,"true or false"))
+}
+
+fn as_string(key: &str, v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        Value::Integer(i) => i.to_string(), // e.g. edition = 2021
+        _ => bad_value(key,"a string")
+    }
+}
+
+fn as_usize(key: &str, v: &Value) -> usize {
+    v.as_integer().filter(|&i| i >= 0).unwrap_or_else(|| bad_value(key,"a number")) as usize
+}
+
+fn as_strings(key: &str, v: &Value) -> Vec<String> {
+    v.as_array().unwrap_or_else(|| bad_value(key,"a list of strings"))
+        .iter().map(|s| as_string(key,s)).collect()
+}
+
+fn as_error_rules(v: &Value) -> ErrorRules {
+    let mut rules = ErrorRules::new();
+    let table = v.as_table().unwrap_or_else(|| bad_value("errors","a table"));
+    for (key,v) in table {
+        match key.as_str() {
+            "max" => rules.max = Some(as_usize(key,v)),
+            "notes" => rules.notes = as_bool(key,v),
+            "help" => rules.help = as_bool(key,v),
+            "hide" => rules.hide = as_strings(key,v),
+            "filters" => rules.filters = as_strings(key,v),
+            _ => info!("config.toml: unknown key '{}' in [errors]",key),
+        }
+    }
+    rules
+}
+
+fn as_imports(v: &Value) -> HashMap<String,String> {
+    let table = v.as_table().unwrap_or_else(|| bad_value("imports","a table"));
+    table.iter().map(|(name,v)| {
+        let style = as_string(name,v);
+        if style != "wild" && style != "macro" {
+            bad_value(name,"\"wild\" or \"macro\"");
+        }
+        (name.clone(),style)
+    }).collect()
+}
+
+impl Config {
+    pub fn read(path: &PathBuf) -> Config {
+        let mut config = Config { errors: ErrorRules::new(), ..Config::default() };
+        if ! path.is_file() {
+            return config;
+        }
+        let body = fs::read_to_string(path).or_die("cannot read config.toml");
+        let toml = body.parse::<Value>().or_then_die(|e| format!("cannot parse config.toml: {}",e));
+        for (key,v) in toml.as_table().unwrap() {
+            match key.as_str() {
+                "edition" => config.edition = Some(as_string(key,v)),
+                "static" => config.build_static = Some(as_bool(key,v)),
+                "optimize" => config.optimize = Some(as_bool(key,v)),
+                "externs" => config.externs = as_strings(key,v),
+                "editor" => config.editor = Some(as_string(key,v)),
+                "simplify" => config.simplify = Some(as_bool(key,v)),
+                "raw" => config.raw = Some(as_bool(key,v)),
+                "auto_add" => config.auto_add = Some(as_bool(key,v)),
+                "rpath" => config.rpath = Some(as_bool(key,v)),
+                "registry" => config.registry = Some(as_string(key,v)),
+                "install_dir" => config.install_dir = Some(as_string(key,v)),
+                "errors" => config.errors = as_error_rules(v),
+                "imports" => config.imports = as_imports(v),
+                _ => info!("config.toml: unknown key '{}'",key),
+            }
+        }
+        config
+    }
+
+    // the defaults as runner flags
+    pub fn default_args(&self) -> Vec<String> {
+        let mut res = Vec::new();
+        if let Some(ref edition) = self.edition {
+            res.push("--edition".to_string());
+            res.push(edition.clone());
+        }
+        // false is what runner does anyway, and a flag here couldn't be undone later
+        if self.build_static == Some(true) {
+            res.push("--static".to_string());
+        }
+        if self.optimize == Some(true) {
+            res.push("--optimize".to_string());
+        }
+        for c in &self.externs {
+            res.push("--extern".to_string());
+            res.push(c.clone());
+        }
+        if self.simplify == Some(false) {
+            res.push("--no-simplify".to_string());
+        }
+        if self.raw == Some(true) {
+            res.push("--raw".to_string());
+        }
+        if self.auto_add == Some(true) {
+            res.push("--auto-add".to_string());
+        }
+        if self.rpath == Some(true) {
+            res.push("--rpath".to_string());
+        }
+        if let Some(ref registry) = self.registry {
+            res.push("--registry".to_string());
+            res.push(registry.clone());
+        }
+        res
+    }
+}
@@ -7,8 +7,81 @@ use es::traits::*;
 use dirs;
 
 lazy_static! {
-    pub static ref RUSTUP_LIB: String = es::shell("rustc --print sysroot") + "/lib";
-    pub static ref UNSTABLE: bool = RUSTUP_LIB.find("nightly").is_some();
+    // --sysroot (which becomes RUNNER_SYSROOT) or what rustc says
+    pub static ref SYSROOT: String = env::var("RUNNER_SYSROOT").ok().filter(|s| ! s.is_empty())
+        .unwrap_or_else(|| es::shell("rustc --print sysroot"));
+    // where the shared libstd lives, which dynamically linked programs need
+    pub static ref RUSTUP_LIB: String = std_lib_dir(Path::new(&*SYSROOT)).display().to_string();
+    pub static ref UNSTABLE: bool = SYSROOT.find("nightly").is_some();
+    // rustc's version and host, asked for once
+    static ref RUSTC_VV: String = es::shell("rustc -vV");
+    // the target triple rustc builds for, like x86_64-pc-windows-msvc
+    pub static ref HOST: String = RUSTC_VV.lines()
+        .find_map(|l| l.strip_prefix("host: ").map(|h| h.to_string()))
+        .unwrap_or_default();
+    // like 'rustc 1.80.0 (051478957 2024-07-21)'
+    pub static ref RUSTC_RELEASE: String = RUSTC_VV.lines().next().unwrap_or_default().to_string();
+}
+
+// msvc and gnu toolchains on Windows can't use each other's DLLs
+pub fn windows_abi() -> Option<&'static str> {
+    // without asking rustc, elsewhere
+    if ! cfg!(windows) || ! HOST.contains("-windows-") {
+        None
+    } else if HOST.ends_with("-msvc") {
+        Some("msvc")
+    } else if HOST.ends_with("-gnullvm") {
+        Some("gnullvm")
+    } else {
+        Some("gnu")
+    }
+}
+
+// what rustc links against for a crate's DLL on Windows: foo.dll.lib for msvc,
+// libfoo.dll.a for gnu
+pub fn import_library(crate_name: &str) -> Option<String> {
+    match windows_abi()? {
+        "msvc" => Some(format!("{}.dll.lib",crate_name)),
+        _ => Some(format!("lib{}.dll.a",crate_name))
+    }
+}
+
+// -Z options need a nightly rustc, unless RUSTC_BOOTSTRAP lets a stable one pretend
+pub fn is_nightly() -> bool {
+    if *UNSTABLE || env::var("RUSTC_BOOTSTRAP").is_ok_and(|v| ! v.is_empty()) {
+        return true;
+    }
+    let version = es::shell("rustc --version");
+    version.contains("-nightly") || version.contains("-dev")
+}
+
+pub fn has_libstd(dir: &Path) -> bool {
+    use std::env::consts::{DLL_PREFIX,DLL_SUFFIX};
+    let prefix = format!("{}std-",DLL_PREFIX);
+    fs::read_dir(dir).map(|entries| entries.flatten().any(|e| {
+        let name = e.file_name().to_string_lossy().to_string();
+        name.starts_with(&prefix) && name.ends_with(DLL_SUFFIX)
+    })).unwrap_or(false)
+}
+
+// rustup used to put libstd in the sysroot's lib, but it (like distro packages,
+// Nix and Homebrew) may only have it with the host target's libraries
+fn std_lib_dir(sysroot: &Path) -> PathBuf {
+    // Windows looks for DLLs on the PATH, and the toolchain keeps them in bin
+    let bin = sysroot.join("bin");
+    if cfg!(windows) && has_libstd(&bin) {
+        return bin;
+    }
+    let lib = sysroot.join("lib");
+    if has_libstd(&lib) {
+        return lib;
+    }
+    let target_lib = lib.join("rustlib").join(&*HOST).join("lib");
+    if has_libstd(&target_lib) {
+        target_lib
+    } else {
+        lib
+    }
 }
 
 pub fn proper_crate_name(crate_name: &str) -> String {
@@ -65,13 +138,73 @@ pub fn crate_info (cargo_toml: &Path) -> CrateInfo {
         .get("package").unwrap();
     let name = package.get("name").unwrap()
         .as_str().unwrap().to_string();
-    let edition = match package.get("edition") {
-        None => "2015",
-        Some(e) => e.as_str().unwrap()
-    }.to_string();
+    let edition = package_edition(package, cargo_toml);
     CrateInfo {
         name, edition,
     }
 }
 
+// 'edition.workspace = true' means it's in [workspace.package] of this
+// Cargo.toml, or one further up
+fn package_edition(package: &toml::Value, cargo_toml: &Path) -> String {
+    match package.get("edition") {
+        None => "2015".into(),
+        Some(toml::Value::String(e)) => e.clone(),
+        Some(_) => cargo_toml.ancestors().skip(1)
+            .filter_map(|dir| fs::read_to_string(dir.join("Cargo.toml")).ok())
+            .filter_map(|body| body.parse::<toml::Value>().ok())
+            .find_map(|toml| toml.get("workspace")
+                .and_then(|w| w.get("package"))
+                .and_then(|p| p.get("edition"))
+                .and_then(|e| e.as_str())
+                .map(|e| e.to_string()))
+            .unwrap_or_else(|| "2015".into())
+    }
+}
 
+// the edition of the Cargo project a source file is in, if it is in one.
+// A workspace's own Cargo.toml may have no [package], so keep looking
+pub fn project_edition(file: &Path) -> Option<String> {
+    let file = file.canonicalize().ok()?;
+    let mut dir = file.parent()?.to_path_buf();
+    loop {
+        let (path,cargo_toml) = cargo_dir(&dir).ok()?;
+        let has_package = fs::read_to_string(&cargo_toml).ok()
+            .and_then(|body| body.parse::<toml::Value>().ok())
+            .is_some_and(|toml| toml.get("package").is_some_and(|p| p.get("name").is_some()));
+        if has_package {
+            return Some(crate_info(&cargo_toml).edition);
+        }
+        dir = path.parent()?.to_path_buf();
+    }
+}
+
+// the newest version of a crate which Cargo has downloaded for any project, like
+// ~/.cargo/registry/src/index.crates.io-6f17d22bba15001f/regex-1.10.2
+pub fn find_in_cargo_cache(name: &str) -> Option<PathBuf> {
+    let names = [name.to_string(), name.replace('_',"-")];
+    let registries = fs::read_dir(cargo_home().join("registry").join("src")).ok()?;
+    registries.flatten()
+        .flat_map(|registry| fs::read_dir(registry.path()).into_iter().flatten().flatten())
+        .filter_map(|entry| {
+            let dir = entry.file_name().to_str()?.to_string();
+            let version = names.iter().find_map(|n| dir.strip_prefix(n.as_str())?.strip_prefix('-'))?;
+            let version = semver::Version::parse(version).ok()?;
+            Some((version, entry.path()))
+        })
+        .max_by(|a,b| a.0.cmp(&b.0))
+        .map(|(_,path)| path)
+}
+
+// the features a crate enables by default, which rustc must be told about
+pub fn default_features(cargo_toml: &Path) -> Vec<String> {
+    let toml = fs::read_to_string(cargo_toml).ok().and_then(|body| body.parse::<toml::Value>().ok());
+    toml.as_ref()
+        .and_then(|t| t.get("features")).and_then(|f| f.get("default")).and_then(|d| d.as_array())
+        .into_iter().flatten()
+        .filter_map(|f| f.as_str())
+        // not optional dependencies or features of other crates
+        .filter(|f| ! f.contains(':') && ! f.contains('/'))
+        .map(|f| f.to_string())
+        .collect()
+}
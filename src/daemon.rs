@@ -0,0 +1,400 @@
+// runner --daemon keeps a process in the background which builds snippets, so
+// that each runner doesn't have to start cold (finding the sysroot means running
+// rustc, for instance). Only on Unix.
+//
+// A runner which finds the daemon's socket sends its working directory, command
+// line, environment and standard streams (as file descriptors) over it. The daemon
+// forks a child which does the usual work, writing to the client's streams, and
+// sends back the command for running the program. The client runs that itself,
+// so the program has the terminal. Last comes the exit status of the child.
+#[cfg(unix)]
+use es::traits::*;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::sync::OnceLock;
+
+#[cfg(unix)]
+use std::{fs, io};
+#[cfg(unix)]
+use std::io::prelude::*;
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd,RawFd};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener,UnixStream};
+#[cfg(unix)]
+use std::os::unix::process::ExitStatusExt;
+
+#[cfg(unix)]
+use crate::cache;
+#[cfg(unix)]
+use crate::strutil;
+use crate::platform::Redirect;
+use crate::subcommands;
+
+// in a child of the daemon, the client's command line and connection
+static CLIENT_ARGS: OnceLock<Vec<String>> = OnceLock::new();
+#[cfg(unix)]
+static CLIENT: OnceLock<UnixStream> = OnceLock::new();
+
+// a daemon only knows the toolchain it was started with, so there's one for each:
+// rustup picks it with RUSTUP_TOOLCHAIN or a rust-toolchain file, and runner's
+// --sysroot is passed on as RUNNER_SYSROOT
+#[cfg(unix)]
+fn toolchain_key() -> String {
+    let mut parts: Vec<String> = ["RUSTUP_TOOLCHAIN","RUNNER_SYSROOT"].iter()
+        .map(|v| env::var(v).unwrap_or_default())
+        .collect();
+    let cwd = env::current_dir().or_die("cannot get current directory");
+    let file = cwd.ancestors()
+        .flat_map(|dir| ["rust-toolchain.toml","rust-toolchain"].map(|f| dir.join(f)))
+        .find(|f| f.is_file());
+    if let Some(file) = file {
+        parts.push(fs::read_to_string(file).unwrap_or_default());
+    }
+    if parts.iter().all(|p| p.is_empty()) {
+        "default".into()
+    } else {
+        let parts: Vec<&str> = parts.iter().map(|p| p.as_str()).collect();
+        strutil::content_hash(&parts)
+    }
+}
+
+#[cfg(unix)]
+fn socket_path() -> PathBuf {
+    cache::base_directory().join(format!("daemon-{}.sock",toolchain_key()))
+}
+
+#[cfg(unix)]
+fn pid_path() -> PathBuf {
+    cache::base_directory().join(format!("daemon-{}.pid",toolchain_key()))
+}
+
+// our command line, or the client's if we are working for it (with any
+// subcommand turned into flags)
+pub fn command_line_args() -> Vec<String> {
+    subcommands::expand(CLIENT_ARGS.get().cloned().unwrap_or_else(|| env::args().collect()))
+}
+
+#[cfg(unix)]
+pub fn serving() -> bool {
+    CLIENT.get().is_some()
+}
+
+#[cfg(not(unix))]
+pub fn serving() -> bool {
+    false
+}
+
+// fields are separated by NUL; a list is preceded by its length
+#[cfg(unix)]
+fn encode(fields: &[String]) -> Vec<u8> {
+    let mut res = Vec::new();
+    for f in fields {
+        res.extend(f.as_bytes());
+        res.push(0);
+    }
+    res
+}
+
+#[cfg(unix)]
+fn decode(bytes: &[u8]) -> Vec<String> {
+    let text = String::from_utf8_lossy(bytes);
+    let mut res: Vec<String> = text.split('\0').map(|s| s.to_string()).collect();
+    res.pop(); // after the last NUL
+    res
+}
+
+#[cfg(unix)]
+fn take_list(fields: &mut std::vec::IntoIter<String>) -> Vec<String> {
+    let n = fields.next().and_then(|n| n.parse().ok()).unwrap_or(0);
+    fields.by_ref().take(n).collect()
+}
+
+#[cfg(unix)]
+fn with_list(fields: &mut Vec<String>, list: Vec<String>) {
+    fields.push(list.len().to_string());
+    fields.extend(list);
+}
+
+#[cfg(unix)]
+fn write_message(mut out: &UnixStream, tag: u8, payload: &[u8]) -> io::Result<()> {
+    let mut msg = vec![tag];
+    msg.extend(&(payload.len() as u32).to_le_bytes());
+    msg.extend(payload);
+    out.write_all(&msg)
+}
+
+#[cfg(unix)]
+fn read_message(mut inp: &UnixStream) -> io::Result<(u8,Vec<u8>)> {
+    let mut header = [0u8; 5];
+    inp.read_exact(&mut header)?;
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&header[1..]);
+    let mut payload = vec![0u8; u32::from_le_bytes(len) as usize];
+    inp.read_exact(&mut payload)?;
+    Ok((header[0],payload))
+}
+
+// the request header goes with the client's stdin, stdout and stderr
+#[cfg(unix)]
+fn send_with_fds(conn: &UnixStream, bytes: &[u8], fds: &[RawFd]) -> io::Result<()> {
+    use std::mem;
+    let fds_len = mem::size_of_val(fds) as libc::c_uint;
+    unsafe {
+        let mut iov = libc::iovec { iov_base: bytes.as_ptr() as *mut libc::c_void, iov_len: bytes.len() };
+        let mut control = vec![0u8; libc::CMSG_SPACE(fds_len) as usize];
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control.len() as _;
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(fds_len) as _;
+        std::ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, fds.len());
+        if libc::sendmsg(conn.as_raw_fd(), &msg, 0) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn receive_with_fds(conn: &UnixStream, bytes: &mut [u8]) -> io::Result<Vec<RawFd>> {
+    use std::mem;
+    let mut fds = [-1 as RawFd; 3];
+    let fds_len = mem::size_of_val(&fds) as libc::c_uint;
+    unsafe {
+        let mut iov = libc::iovec { iov_base: bytes.as_mut_ptr() as *mut libc::c_void, iov_len: bytes.len() };
+        let mut control = vec![0u8; libc::CMSG_SPACE(fds_len) as usize];
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = control.len() as _;
+        let n = libc::recvmsg(conn.as_raw_fd(), &mut msg, 0);
+        if n < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if n as usize != bytes.len() || cmsg.is_null() || (*cmsg).cmsg_type != libc::SCM_RIGHTS {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,"bad request"));
+        }
+        std::ptr::copy_nonoverlapping(libc::CMSG_DATA(cmsg) as *const RawFd, fds.as_mut_ptr(), fds.len());
+    }
+    Ok(fds.to_vec())
+}
+
+// pass this runner's work to the daemon, if there is one, returning the exit code
+#[cfg(unix)]
+pub fn forward() -> Option<i32> {
+    let conn = UnixStream::connect(socket_path()).ok()?;
+    let cwd = env::current_dir().or_die("cannot get current directory");
+    let mut fields = vec![cwd.to_string_lossy().to_string()];
+    with_list(&mut fields, env::args().collect());
+    with_list(&mut fields, env::vars_os()
+        .map(|(k,v)| format!("{}={}",k.to_string_lossy(),v.to_string_lossy()))
+        .collect());
+    let payload = encode(&fields);
+    send_with_fds(&conn, &(payload.len() as u32).to_le_bytes(), &[0,1,2]).or_die("cannot talk to runner daemon");
+    (&conn).write_all(&payload).or_die("cannot talk to runner daemon");
+
+    let mut run = None;
+    loop {
+        let (tag,payload) = read_message(&conn).or_die("lost connection to runner daemon");
+        if tag == b'R' {
+            run = Some(decode(&payload));
+        } else {
+            let code = decode(&payload).first().and_then(|c| c.parse().ok()).unwrap_or(1);
+            if code != 0 || run.is_none() {
+                return Some(code);
+            }
+            break;
+        }
+    }
+    let mut fields = run.unwrap().into_iter();
+    let mut builder = process::Command::new(fields.next().unwrap_or_default());
+    builder.args(take_list(&mut fields));
+    for var in take_list(&mut fields) {
+        if let Some(idx) = var.find('=') {
+            builder.env(&var[0..idx], &var[idx+1..]);
+        }
+    }
+    let dir = fields.next().unwrap_or_default();
+    if ! dir.is_empty() {
+        builder.current_dir(dir);
+    }
+    // the output files are opened here, where the program runs
+    let mut optional = || fields.next().filter(|f| ! f.is_empty());
+    let redirect = Redirect { out: optional(), err: optional(), append: optional().is_some() };
+    redirect.apply(&mut builder);
+    let status = crate::platform::run_program(&mut builder).or_die("can't run program");
+    Some(crate::platform::exit_code(&status))
+}
+
+#[cfg(not(unix))]
+pub fn forward() -> Option<i32> {
+    None
+}
+
+// a child of the daemon gives the program back to its client to run
+#[cfg(unix)]
+pub fn run_in_client(builder: &process::Command, redirect: &Redirect) {
+    let mut fields = vec![builder.get_program().to_string_lossy().to_string()];
+    with_list(&mut fields, builder.get_args().map(|a| a.to_string_lossy().to_string()).collect());
+    with_list(&mut fields, builder.get_envs()
+        .filter_map(|(k,v)| v.map(|v| format!("{}={}",k.to_string_lossy(),v.to_string_lossy())))
+        .collect());
+    let dir = builder.get_current_dir().map(|d| d.to_string_lossy().to_string());
+    for field in [dir, redirect.out.clone(), redirect.err.clone()] {
+        fields.push(field.unwrap_or_default());
+    }
+    fields.push(if redirect.append {"append".into()} else {String::new()});
+    // if the client has gone away, there's nobody to tell
+    let _ = write_message(CLIENT.get().unwrap(), b'R', &encode(&fields));
+}
+
+#[cfg(not(unix))]
+pub fn run_in_client(_builder: &process::Command, _redirect: &Redirect) {
+}
+
+// in the child: become the client's runner, with its streams, directory,
+// environment and command line
+#[cfg(unix)]
+fn become_client(conn: UnixStream, fds: Vec<RawFd>, fields: Vec<String>) {
+    unsafe {
+        for (i,fd) in fds.into_iter().enumerate() {
+            libc::dup2(fd, i as libc::c_int);
+            libc::close(fd);
+        }
+    }
+    let mut fields = fields.into_iter();
+    let cwd = fields.next().unwrap_or_default();
+    let args = take_list(&mut fields);
+    let vars = take_list(&mut fields);
+    for (k,_) in env::vars_os() {
+        env::remove_var(k);
+    }
+    for var in vars {
+        if let Some(idx) = var.find('=') {
+            env::set_var(&var[0..idx], &var[idx+1..]);
+        }
+    }
+    env::set_current_dir(&cwd).or_die("cannot change to client's directory");
+    // a fresh child, so these are not set yet
+    let _ = CLIENT_ARGS.set(args);
+    let _ = CLIENT.set(conn);
+}
+
+#[cfg(unix)]
+fn serve(conn: UnixStream, run: fn()) -> io::Result<()> {
+    let mut len = [0u8; 4];
+    let fds = receive_with_fds(&conn, &mut len)?;
+    let mut payload = vec![0u8; u32::from_le_bytes(len) as usize];
+    (&conn).read_exact(&mut payload)?;
+    let fields = decode(&payload);
+
+    // the daemon has no threads (forking with them isn't safe), so the waiting is
+    // done by a child, which forks the one doing the work
+    let pid = unsafe { libc::fork() };
+    if pid == 0 {
+        unsafe { libc::signal(libc::SIGCHLD, libc::SIG_DFL); }
+        let worker = unsafe { libc::fork() };
+        if worker == 0 {
+            become_client(conn, fds, fields);
+            run();
+            io::stdout().flush().or_die("i/o?");
+            process::exit(0);
+        }
+        for fd in fds {
+            unsafe { libc::close(fd); }
+        }
+        let code = if worker < 0 {
+            1
+        } else {
+            let mut status = 0;
+            unsafe { libc::waitpid(worker, &mut status, 0); }
+            let status = process::ExitStatus::from_raw(status);
+            status.code().unwrap_or_else(|| 128 + status.signal().unwrap_or(0))
+        };
+        let _ = write_message(&conn, b'X', &encode(&[code.to_string()]));
+        process::exit(0);
+    }
+    for fd in fds {
+        unsafe { libc::close(fd); }
+    }
+    if pid < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// start the daemon in the background; run is what a normal runner does
+#[cfg(unix)]
+pub fn start(run: fn()) {
+    let socket = socket_path();
+    if UnixStream::connect(&socket).is_ok() {
+        es::quit("the runner daemon is already running");
+    }
+    fs::create_dir_all(cache::base_directory()).or_die("cannot create runner directory");
+    let _ = fs::remove_file(&socket);
+    let listener = UnixListener::bind(&socket).or_die("cannot create daemon socket");
+    // the expensive things which every runner needs
+    lazy_static::initialize(&crate::crate_utils::RUSTUP_LIB);
+    lazy_static::initialize(&crate::crate_utils::UNSTABLE);
+    lazy_static::initialize(&crate::crate_utils::HOST);
+    lazy_static::initialize(&crate::crate_utils::RUSTC_RELEASE);
+    cache::warm_up();
+
+    let log = fs::OpenOptions::new().create(true).append(true)
+        .open(cache::base_directory().join("daemon.log")).or_die("cannot open daemon log");
+    let null = fs::File::open("/dev/null").or_die("cannot open /dev/null");
+    let pid = unsafe { libc::fork() };
+    if pid < 0 {
+        es::quit("cannot start daemon");
+    }
+    if pid > 0 {
+        info!("runner daemon started (pid {})",pid);
+        return;
+    }
+    unsafe {
+        libc::setsid();
+        libc::dup2(null.as_raw_fd(), 0);
+        libc::dup2(log.as_raw_fd(), 1);
+        libc::dup2(log.as_raw_fd(), 2);
+        // finished children go away by themselves
+        libc::signal(libc::SIGCHLD, libc::SIG_IGN);
+    }
+    fs::write(pid_path(), process::id().to_string()).or_die("cannot write daemon pid");
+    for conn in listener.incoming() {
+        if let Err(e) = conn.and_then(|conn| serve(conn, run)) {
+            eprintln!("runner daemon: {}",e);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub fn start(_run: fn()) {
+    es::quit("the runner daemon needs Unix");
+}
+
+#[cfg(unix)]
+pub fn stop() {
+    let pid = fs::read_to_string(pid_path()).ok().and_then(|p| p.trim().parse().ok());
+    match pid {
+        Some(pid) if UnixStream::connect(socket_path()).is_ok() => {
+            unsafe { libc::kill(pid, libc::SIGTERM); }
+            info!("stopped runner daemon (pid {})",pid);
+        },
+        _ => info!("the runner daemon is not running")
+    }
+    let _ = fs::remove_file(socket_path());
+    let _ = fs::remove_file(pid_path());
+}
+
+#[cfg(not(unix))]
+pub fn stop() {
+    es::quit("the runner daemon needs Unix");
+}
@@ -0,0 +1,395 @@
+// making rustc's error messages friendlier for snippets
+use std::env;
+use std::path::{Path,PathBuf};
+use json::JsonValue;
+
+// the colour escapes which rustc uses, removed so that we can see the text
+pub fn strip_ansi(text: &str) -> String {
+    let mut res = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            // CSI sequence: ESC [ params final-letter
+            for c in chars.by_ref() {
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+        } else {
+            res.push(c);
+        }
+    }
+    res
+}
+
+// a line quoting the source, like ' 24 |     map.set("hello","dolly");'
+fn is_source_line(line: &str) -> bool {
+    let line = line.trim_start();
+    let digits = line.find(|c: char| ! c.is_ascii_digit()).unwrap_or(line.len());
+    digits > 0 && line[digits..].trim_start().starts_with('|')
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+// 'std::collections::HashMap' becomes 'HashMap'. Only a 'std::' starting a
+// path and followed by a module name and '::' is shortened, so any colour
+// escapes and the rest of the text are left alone.
+fn simplify_line(text: &str) -> String {
+    let std = "std::";
+    let mut res = String::new();
+    let mut s = text;
+    while let Some(pos) = s.find(std) {
+        let before = &s[0..pos];
+        let rest = &s[pos+std.len()..];
+        let path_start = ! before.ends_with(|c: char| is_ident_char(c) || c == ':');
+        let module_len = rest.find(|c: char| ! is_ident_char(c)).unwrap_or(rest.len());
+        res.push_str(before);
+        if path_start && module_len > 0 && rest[module_len..].starts_with("::") {
+            s = &rest[module_len+2..];
+        } else {
+            res.push_str(std);
+            s = rest;
+        }
+    }
+    res.push_str(s);
+    res
+}
+
+// quoted source lines are left as they are, so that the carets
+// underneath still line up
+pub fn simplify_qualified_names(text: &str) -> String {
+    let mut res = String::new();
+    for line in text.lines() {
+        if is_source_line(&strip_ansi(line)) {
+            res += line;
+        } else {
+            res += &simplify_line(line);
+        }
+        res.push('\n');
+    }
+    res
+}
+
+// the code of the first error, like 'E0425'
+pub fn first_error_code(text: &str) -> Option<String> {
+    text.lines().map(strip_ansi).find_map(|line| {
+        let code = line.strip_prefix("error[")?.split(']').next()?;
+        Some(code.to_string())
+    })
+}
+
+// what rustc says about a crate it doesn't have (E0432, E0433 and E0463),
+// across the versions which word it differently
+const MISSING_CRATE: &[&str] = &[
+    "can't find crate for `",
+    "unresolved module or unlinked crate `",
+    "undeclared crate or module `",
+    "cannot find module or crate `",
+];
+
+// the crates which rustc could not find, in the order it complained about them
+pub fn missing_crates(text: &str) -> Vec<String> {
+    let mut res: Vec<String> = Vec::new();
+    for line in text.lines().map(strip_ansi) {
+        for phrase in MISSING_CRATE {
+            let mut rest = line.as_str();
+            while let Some(pos) = rest.find(phrase) {
+                rest = &rest[pos+phrase.len()..];
+                let name = rest.split('`').next().unwrap_or_default();
+                let builtin = ["std","core","alloc","proc_macro","test","crate","self","super"].contains(&name);
+                if ! name.is_empty() && name.chars().all(is_ident_char) && ! builtin && ! res.iter().any(|n| n == name) {
+                    res.push(name.to_string());
+                }
+            }
+        }
+    }
+    res
+}
+
+// '*' matches any run of characters, '?' any single character
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let (p,t): (Vec<char>,Vec<char>) = (pattern.chars().collect(), text.chars().collect());
+    let (mut pi, mut ti) = (0,0);
+    let mut star = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        } else if pi < p.len() && p[pi] == '*' {
+            star = Some((pi,ti));
+            pi += 1;
+        } else if let Some((spi,sti)) = star {
+            // let the last star swallow one more character
+            pi = spi + 1;
+            ti = sti + 1;
+            star = Some((spi,sti+1));
+        } else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|&c| c == '*')
+}
+
+// how much of rustc's output to show, from the [errors] table in config.toml:
+//
+//    [errors]
+//    max = 3                  # only the first few errors and warnings
+//    notes = false            # leave out notes
+//    help = false             # leave out help
+//    hide = ["E0601"]         # leave out errors with these codes
+//    filters = ["*never used*"]  # leave out messages matching these patterns
+#[derive(Default)]
+pub struct ErrorRules {
+    pub max: Option<usize>,
+    pub notes: bool,
+    pub help: bool,
+    pub hide: Vec<String>,
+    pub filters: Vec<String>,
+}
+
+// the header of an error or warning, like 'error[E0425]: cannot find value'
+fn is_header(line: &str) -> bool {
+    line.starts_with("error") || line.starts_with("warning")
+}
+
+// the summary at the end, like 'error: aborting due to 2 previous errors'
+fn is_summary(line: &str) -> bool {
+    line.starts_with("error: aborting") || (line.starts_with("warning: ") && line.ends_with(" emitted"))
+}
+
+impl ErrorRules {
+    pub fn new() -> ErrorRules {
+        ErrorRules { notes: true, help: true, ..Default::default() }
+    }
+
+    fn wanted(&self, header: &str) -> bool {
+        let code = header.find("[E").and_then(|i| header[i+1..].split(']').next());
+        if code.is_some_and(|code| self.hide.iter().any(|h| h == code)) {
+            return false;
+        }
+        let message = header.split_once(": ").map(|(_,m)| m).unwrap_or(header);
+        ! self.filters.iter().any(|f| wildcard_match(f,message))
+    }
+
+    // leave out 'note:' and 'help:' sections, and '= note:' lines, if asked
+    fn strip_sections(&self, lines: &[&str]) -> Vec<String> {
+        let mut res = Vec::new();
+        let mut skipping = false;
+        let mut skip_indent = None;
+        for (i,line) in lines.iter().enumerate() {
+            let plain = strip_ansi(line);
+            let trimmed = plain.trim_start();
+            let indent = plain.len() - trimmed.len();
+            // a new section begins at the margin, but so do the line numbers of long source lines
+            let at_margin = indent == 0 && ! trimmed.starts_with(|c: char| c.is_ascii_digit());
+            if i > 0 && at_margin && ! trimmed.is_empty() {
+                skipping = (! self.notes && trimmed.starts_with("note:"))
+                    || (! self.help && trimmed.starts_with("help:"));
+            }
+            if let Some(skip) = skip_indent {
+                // continuation of a long '= note:'
+                if indent > skip && ! trimmed.starts_with('|') && ! trimmed.starts_with('=') {
+                    continue;
+                }
+                skip_indent = None;
+            }
+            if (! self.notes && trimmed.starts_with("= note:")) || (! self.help && trimmed.starts_with("= help:")) {
+                skip_indent = Some(indent);
+                continue;
+            }
+            // keep the blank line which ends a message
+            if ! skipping || (trimmed.is_empty() && i + 1 == lines.len()) {
+                res.push(line.to_string());
+            }
+        }
+        res
+    }
+
+    pub fn apply(&self, text: &str) -> String {
+        // split into messages, each starting with a header
+        let mut messages: Vec<Vec<&str>> = Vec::new();
+        for line in text.lines() {
+            if is_header(&strip_ansi(line)) || messages.is_empty() {
+                messages.push(Vec::new());
+            }
+            messages.last_mut().unwrap().push(line);
+        }
+        let mut res = String::new();
+        let mut count = 0;
+        for message in messages {
+            let header = strip_ansi(message[0]);
+            if is_header(&header) && ! is_summary(&header) {
+                if ! self.wanted(&header) || self.max.is_some_and(|max| count >= max) {
+                    continue;
+                }
+                count += 1;
+            }
+            for line in self.strip_sections(&message) {
+                res += &line;
+                res.push('\n');
+            }
+        }
+        res
+    }
+}
+
+// --color is 'auto', 'always' or 'never'. Auto means colours when stderr is
+// a terminal, unless NO_COLOR is set
+pub fn use_color(color: &str) -> Result<bool,String> {
+    match color {
+        "always" => Ok(true),
+        "never" => Ok(false),
+        "auto" => Ok(isatty::stderr_isatty() && env::var("NO_COLOR").is_err()),
+        _ => Err(format!("--color must be auto, always or never, not '{}'",color))
+    }
+}
+
+// for each line of a generated program, where it came from in the
+// user's snippet as (line, column adjustment)
+pub type LineMap = Vec<Option<(usize,isize)>>;
+
+pub struct SourceMap {
+    pub generated: PathBuf,
+    pub original: PathBuf,
+    lines: LineMap,
+    line_starts: Vec<usize>,
+    contents: String,
+}
+
+impl SourceMap {
+    pub fn new(generated: &Path, original: &Path, lines: LineMap, contents: String) -> SourceMap {
+        let line_starts = contents.split('\n').scan(0, |pos,line| {
+            let start = *pos;
+            *pos += line.len() + 1;
+            Some(start)
+        }).collect();
+        SourceMap {
+            generated: generated.to_path_buf(),
+            original: original.to_path_buf(),
+            lines, line_starts, contents
+        }
+    }
+
+    // 1-based line and column in the generated program to the original
+    pub fn original_position(&self, line: usize, column: usize) -> Option<(usize,usize)> {
+        let (orig,offset) = (*self.lines.get(line.checked_sub(1)?)?)?;
+        Some((orig, (column as isize + offset).max(1) as usize))
+    }
+
+    // rustc columns count characters, but the spans also have byte offsets
+    fn byte_offset(&self, line: usize, column: usize) -> usize {
+        let start = self.line_starts.get(line - 1).cloned().unwrap_or(self.contents.len());
+        let text = &self.contents[start..];
+        start + text.char_indices().nth(column - 1).map(|(i,_)| i).unwrap_or(text.len())
+    }
+
+    fn remap_span(&self, span: &mut JsonValue) {
+        if span["file_name"].as_str() != Some(&self.generated.display().to_string()) {
+            return;
+        }
+        let pos = |l: &str, c: &str| self.original_position(
+            span[l].as_usize().unwrap_or(0), span[c].as_usize().unwrap_or(0)
+        );
+        if let (Some((ls,cs)),Some((le,ce))) = (pos("line_start","column_start"),pos("line_end","column_end")) {
+            span["file_name"] = self.original.display().to_string().into();
+            span["line_start"] = ls.into();
+            span["column_start"] = cs.into();
+            span["line_end"] = le.into();
+            span["column_end"] = ce.into();
+            span["byte_start"] = self.byte_offset(ls,cs).into();
+            span["byte_end"] = self.byte_offset(le,ce).into();
+        }
+        if span["expansion"].is_object() {
+            self.remap_span(&mut span["expansion"]["span"]);
+            self.remap_span(&mut span["expansion"]["def_site_span"]);
+        }
+    }
+
+    // a rustc JSON diagnostic, with spans in the snippet's part of the
+    // generated program pointing back to the snippet. (Spans in the prelude
+    // and wrapper stay as they are, and so does the 'rendered' text)
+    pub fn remap_diagnostic(&self, diagnostic: &mut JsonValue) {
+        for span in diagnostic["spans"].members_mut() {
+            self.remap_span(span);
+        }
+        for child in diagnostic["children"].members_mut() {
+            self.remap_diagnostic(child);
+        }
+    }
+}
+
+// --type assigns the expression to '()', so that the type rustc finds instead
+// is in the note 'expected unit type `()` found struct `Vec<{float}>`', or at
+// least in the label 'expected `()`, found `&str`'. Closures are just '{closure}'
+pub fn found_type(diagnostic: &JsonValue) -> Option<String> {
+    let label = diagnostic["spans"].members()
+        .find(|s| s["is_primary"] == true)?["label"].as_str()?;
+    let found = label.strip_prefix("expected `()`, found ")?;
+    let note = diagnostic["children"].members()
+        .filter_map(|c| c["message"].as_str())
+        .find_map(|m| m.split_once("found ").map(|(_,f)| f));
+    let found = note.unwrap_or(found);
+    // as in 'found struct `Vec<{float}>`', or just 'found integer'
+    let ty = match found.split_once('`') {
+        Some((_,ty)) => ty.trim_end_matches('`'),
+        None if found == "integer" => "{integer}",
+        None if found == "floating-point number" => "{float}",
+        None => found
+    };
+    let mut res = String::new();
+    let mut rest = ty;
+    while let Some(idx) = rest.find("{closure@") {
+        res += &rest[..idx];
+        res += "{closure}";
+        rest = rest[idx..].split_once('}').map(|(_,r)| r).unwrap_or("");
+    }
+    res += rest;
+    Some(res)
+}
+
+// rustc's JSON diagnostics, referring to the snippet where possible.
+// Anything else rustc says is passed through
+fn diagnostics(stderr: &str, source_map: Option<&SourceMap>) -> Vec<JsonValue> {
+    let mut res = Vec::new();
+    for line in stderr.lines() {
+        match json::parse(line) {
+            Ok(mut doc) => {
+                if let Some(source_map) = source_map {
+                    source_map.remap_diagnostic(&mut doc);
+                }
+                res.push(doc);
+            },
+            Err(_) => eprintln!("{}",line)
+        }
+    }
+    res
+}
+
+// --message-format is 'human' or 'json'; JSON diagnostics go to stdout,
+// one per line, as with cargo
+pub fn print_json(stderr: &str, source_map: Option<&SourceMap>) {
+    for doc in diagnostics(stderr, source_map) {
+        println!("{}",doc.dump());
+    }
+}
+
+// --error-format short is one 'file:line:col: error[E0425]: message' line
+// for each diagnostic, as understood by vim's quickfix and emacs compilation-mode
+pub fn print_short(stderr: &str, source_map: Option<&SourceMap>) {
+    for doc in diagnostics(stderr, source_map) {
+        let level = &doc["level"];
+        if level == "failure-note" {
+            continue;
+        }
+        let code = match doc["code"]["code"].as_str() {
+            Some(code) => format!("{}[{}]",level,code),
+            None => level.to_string()
+        };
+        let location = doc["spans"].members().find(|s| s["is_primary"] == true).map(|span|
+            format!("{}:{}:{}: ",span["file_name"],span["line_start"],span["column_start"])
+        ).unwrap_or_default();
+        eprintln!("{}{}: {}",location,code,doc["message"]);
+    }
+}
@@ -0,0 +1,146 @@
+// runner --doc opens documentation for a crate, or for an item in a crate like
+// 'regex::Regex::captures'. The local docs built with the static cache are used
+// if we have them, otherwise the crate's page on docs.rs. The standard library
+// comes from the rust-docs component, or doc.rust-lang.org.
+use std::fs;
+use std::process;
+use std::path::{Path,PathBuf};
+use std::env::consts::{DLL_PREFIX,DLL_SUFFIX};
+use crate::cache;
+use crate::crate_utils;
+use crate::platform;
+
+const STD_CRATES: &[&str] = &["std","core","alloc","proc_macro","test"];
+
+// the item pages rustdoc generates, like 'struct.Regex.html'
+const ITEM_KINDS: &[&str] = &[
+    "struct","enum","trait","fn","macro","type","constant","static","union",
+    "traitalias","derive","attr","primitive","keyword"
+];
+
+// and the anchors for what's inside them, like 'method.captures'
+const MEMBER_KINDS: &[&str] = &[
+    "tymethod","method","variant","structfield","associatedconstant","associatedtype"
+];
+
+fn file_url(path: &Path) -> String {
+    let path = path.display().to_string().replace('\\',"/");
+    if path.starts_with('/') {
+        format!("file://{}",path)
+    } else { // Windows C:/...
+        format!("file:///{}",path)
+    }
+}
+
+// follow the path (without the crate) through the rustdoc layout: modules are
+// directories, items are pages, and members are anchors in those pages.
+// None if the layout doesn't have it, as with re-exports
+fn item_page(docs: &Path, path: &[&str]) -> Option<(PathBuf,Option<String>)> {
+    let mut dir = docs.to_path_buf();
+    for (i,name) in path.iter().enumerate() {
+        if dir.join(name).join("index.html").exists() {
+            dir.push(name);
+            continue;
+        }
+        let page = ITEM_KINDS.iter()
+            .map(|kind| dir.join(format!("{}.{}.html",kind,name)))
+            .find(|p| p.exists())?;
+        return match &path[i+1..] {
+            [] => Some((page,None)),
+            [member] => {
+                let html = fs::read_to_string(&page).ok()?;
+                let anchor = MEMBER_KINDS.iter()
+                    .map(|kind| format!("{}.{}",kind,member))
+                    .find(|a| html.contains(&format!("id=\"{}\"",a)))?;
+                Some((page,Some(anchor)))
+            },
+            _ => None
+        };
+    }
+    Some((dir.join("index.html"),None))
+}
+
+// the page for the path if we can find it, otherwise rustdoc's own search
+fn open_local(docs: &Path, path: &[&str]) {
+    match item_page(docs, path) {
+        Some((page,None)) => platform::open(&page),
+        Some((page,Some(anchor))) => platform::open(format!("{}#{}",file_url(&page),anchor)),
+        None => {
+            let item = path.join("::");
+            info!("no page for '{}', searching for it",item);
+            platform::open(format!("{}?search={}",file_url(&docs.join("index.html")),item));
+        }
+    }
+}
+
+// where rustup put the rust-docs component, otherwise where it would be in the sysroot
+fn std_docs_dir() -> Option<PathBuf> {
+    let index = process::Command::new("rustup").args(["doc","--path"]).output().ok()
+        .filter(|o| o.status.success())
+        .map(|o| PathBuf::from(String::from_utf8_lossy(&o.stdout).trim()))
+        .unwrap_or_else(|| Path::new(&*crate_utils::SYSROOT).join("share/doc/rust/html/index.html"));
+    if index.exists() {
+        index.parent().map(|p| p.to_path_buf())
+    } else {
+        None
+    }
+}
+
+fn open_std_docs(crate_name: &str, path: &[&str]) {
+    if let Some(dir) = std_docs_dir() {
+        open_local(&dir.join(crate_name), path);
+        return;
+    }
+    let mut url = format!("https://doc.rust-lang.org/{}/",crate_name);
+    if ! path.is_empty() {
+        url += "?search=";
+        url += &path.join("::");
+    }
+    info!("rust-docs is not installed, opening {}",url);
+    platform::open(&url);
+}
+
+// With no crate, the documentation for the whole static cache
+pub fn open_docs(name: Option<String>) {
+    let static_cache = cache::static_cache_dir();
+    let name = name.unwrap_or_else(|| {
+        cache::static_cache_dir_check();
+        "static_cache".into()
+    });
+    let mut path: Vec<&str> = name.split("::").collect();
+    let crate_name = path.remove(0);
+    if STD_CRATES.contains(&crate_name) {
+        open_std_docs(crate_name, &path);
+        return;
+    }
+    let the_crate = crate_utils::proper_crate_name(crate_name);
+    let docs = static_cache.join("target").join("doc").join(&the_crate);
+    if docs.join("index.html").exists() {
+        open_local(&docs, &path);
+        return;
+    }
+    let entry = if static_cache.exists() {
+        cache::get_metadata().get_meta_entry(&the_crate)
+            .map(|e| (e.package.clone(), e.version.to_string()))
+    } else {
+        None
+    };
+    let (package,version) = if let Some(entry) = entry {
+        entry
+    } else {
+        let dll = format!("{}{}{}",DLL_PREFIX,the_crate,DLL_SUFFIX);
+        if cache::dynamic_cache_dir().join(dll).exists() {
+            info!("'{}' is only in the dynamic cache",crate_name);
+        } else {
+            info!("'{}' is not in either cache",crate_name);
+        }
+        (crate_name.to_string(), "latest".to_string())
+    };
+    let mut url = format!("https://docs.rs/{}/{}/{}/",package,version,the_crate);
+    if ! path.is_empty() {
+        url += "?search=";
+        url += &path.join("::");
+    }
+    info!("no local documentation, opening {}",url);
+    platform::open(&url);
+}
@@ -0,0 +1,122 @@
+// runner --doctor checks that what runner needs is there, and says what to do
+// about anything which isn't: the Rust tools, the library path used to run
+// dynamically linked programs, the runner directory and the static cache.
+use std::path::Path;
+use std::process::Command;
+
+use crate::cache;
+use crate::crate_utils;
+
+#[derive(Default)]
+struct Doctor {
+    problems: usize,
+}
+
+impl Doctor {
+    fn ok(&self, what: &str) {
+        println!("ok       {}",what);
+    }
+
+    fn problem(&mut self, what: &str, fix: &str) {
+        println!("PROBLEM  {}",what);
+        println!("         {}",fix);
+        self.problems += 1;
+    }
+}
+
+// the output of a command, if it ran successfully
+fn output(program: &str, args: &[&str]) -> Option<String> {
+    let o = Command::new(program).args(args).output().ok()?;
+    if o.status.success() {
+        Some(String::from_utf8_lossy(&o.stdout).trim().to_string())
+    } else {
+        None
+    }
+}
+
+fn check_tools(doc: &mut Doctor) -> Option<String> {
+    let rustc = output("rustc",&["-vV"]);
+    match rustc {
+        Some(ref v) => doc.ok(v.lines().next().unwrap_or("rustc")),
+        None => doc.problem("rustc is not on PATH",
+            "install Rust (https://rustup.rs), or add the directory containing rustc to PATH"),
+    }
+    match output("cargo",&["--version"]) {
+        Some(v) => doc.ok(&v),
+        None => doc.problem("cargo is not on PATH",
+            "runner needs cargo for the static cache: install it along with rustc"),
+    }
+    rustc
+}
+
+fn check_library_path(doc: &mut Doctor) {
+    let lib = Path::new(&*crate_utils::RUSTUP_LIB);
+    if crate_utils::has_libstd(lib) {
+        doc.ok(&format!("the standard library for dynamic linking is in {}",lib.display()));
+    } else {
+        doc.problem(&format!("no shared libstd in the sysroot {}",*crate_utils::SYSROOT),
+            "dynamically linked programs will not run: give the right one with --sysroot, or build with -s");
+    }
+}
+
+fn check_runner_directory(doc: &mut Doctor) {
+    let home = cache::runner_directory();
+    if ! home.is_dir() {
+        doc.ok(&format!("{} will be created when runner first runs",home.display()));
+        return;
+    }
+    let mut missing = Vec::new();
+    if ! home.join("prelude").is_file() {
+        missing.push("prelude");
+    }
+    if ! home.join("bin").is_dir() {
+        missing.push("bin");
+    }
+    if ! cache::dynamic_cache_dir().is_dir() {
+        missing.push("dy-cache");
+    }
+    if missing.is_empty() {
+        doc.ok(&format!("runner directory {}",home.display()));
+    } else {
+        doc.problem(&format!("{} is missing {}",home.display(),missing.join(", ")),
+            "runner only sets up a new directory: move this one aside and run runner again");
+    }
+}
+
+fn check_static_cache(doc: &mut Doctor, rustc: Option<&str>) {
+    let static_cache = cache::static_cache_dir();
+    if ! static_cache.is_dir() {
+        doc.ok("no static cache yet ('runner --add CRATE' makes one)");
+        return;
+    }
+    if ! static_cache.join("Cargo.toml").is_file() {
+        doc.problem(&format!("{} has no Cargo.toml",static_cache.display()),
+            "move the static cache aside and make a new one with 'runner --add CRATE'");
+        return;
+    }
+    let current = rustc.and_then(|v| v.lines().next());
+    match (cache::static_cache_rustc(), current) {
+        (Some(built), Some(current)) if built != current => {
+            doc.problem(&format!("the static cache was built with {}, not {}",built,current),
+                "rebuild it with 'runner --build'");
+        },
+        _ => doc.ok(&format!("static cache {}",static_cache.display())),
+    }
+}
+
+// returns the exit code: 1 if anything is wrong
+pub fn doctor() -> i32 {
+    let mut doc = Doctor::default();
+    let rustc = check_tools(&mut doc);
+    if rustc.is_some() {
+        check_library_path(&mut doc);
+    }
+    check_runner_directory(&mut doc);
+    check_static_cache(&mut doc, rustc.as_deref());
+    if doc.problems > 0 {
+        println!("{} problem{} found",doc.problems,if doc.problems == 1 {""} else {"s"});
+        1
+    } else {
+        0
+    }
+}
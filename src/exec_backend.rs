@@ -0,0 +1,73 @@
+// Where the compiled program runs. Usually that's here, but with --docker it runs
+// in a container from the given image, with the current directory mounted read-only.
+// That's for trying a snippet with another libc, or keeping it away from everything
+// else. Programs for a container are built statically, C library and all, since neither
+// the dynamic cache nor a matching libc need be there.
+use es::traits::*;
+use std::env;
+use std::path::{Path,PathBuf};
+use std::process::Command;
+
+// where the program goes in the container
+const CONTAINER_BIN: &str = "/runner";
+
+pub enum ExecBackend {
+    Local,
+    Docker(String),
+}
+
+impl ExecBackend {
+    pub fn new(args: &lapp::Args) -> ExecBackend {
+        match args.get_string_result("docker") {
+            Ok(image) => ExecBackend::Docker(image),
+            Err(_) => ExecBackend::Local
+        }
+    }
+
+    pub fn is_local(&self) -> bool {
+        matches!(self, ExecBackend::Local)
+    }
+
+    // the command which runs the program in this directory (if not the current one),
+    // with these environment variables and arguments
+    pub fn command(&self, program: &Path, dir: Option<&Path>, envs: &[(String,String)], args: &[String]) -> Command {
+        let here = env::current_dir().or_die("cannot get current directory");
+        match self {
+            ExecBackend::Local => {
+                let mut builder = if let Some(dir) = dir {
+                    // the program itself must still be found from here
+                    let mut builder = Command::new(here.join(program));
+                    builder.current_dir(dir);
+                    builder
+                } else {
+                    Command::new(program)
+                };
+                builder.envs(envs.iter().cloned());
+                builder.args(args);
+                builder
+            },
+            ExecBackend::Docker(image) => {
+                let name = program.file_name().or_die("no program name?");
+                let inside = Path::new(CONTAINER_BIN).join(name);
+                let mut builder = Command::new("docker");
+                builder.args(["run","--rm","--init","-i"]);
+                if isatty::stdin_isatty() && isatty::stdout_isatty() {
+                    builder.arg("-t");
+                }
+                builder.arg("-v").arg(format!("{}:{}:ro",here.join(program).display(),inside.display()));
+                builder.arg("-v").arg(format!("{}:{}:ro",here.display(),here.display()));
+                // a directory from --chdir outside this one is mounted as well
+                let workdir: PathBuf = dir.map(|d| here.join(d)).unwrap_or_else(|| here.clone());
+                if ! workdir.starts_with(&here) {
+                    builder.arg("-v").arg(format!("{}:{}:ro",workdir.display(),workdir.display()));
+                }
+                builder.arg("-w").arg(&workdir);
+                for (key,value) in envs {
+                    builder.arg("-e").arg(format!("{}={}",key,value));
+                }
+                builder.arg(image).arg(&inside).args(args);
+                builder
+            }
+        }
+    }
+}
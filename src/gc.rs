@@ -0,0 +1,272 @@
+// runner --cache-stats shows how much disk the runner directory takes, and where.
+// runner --gc removes what runner can make again when needed: compiled programs (and
+// the store they are linked from), incremental build state, link plans, local crates
+// and --compare builds, least recently used first. The crates in the caches are
+// left alone ('runner --cleanup' empties the static cache's build). runner --clean-bin
+// only looks at the programs, removing those whose source has gone as well as those
+// not run for a while.
+use std::collections::BTreeMap;
+use std::env::consts::EXE_SUFFIX;
+use std::fs;
+use std::path::{Path,PathBuf};
+use std::time::{Duration,SystemTime};
+
+use crate::cache;
+use crate::logging;
+
+// what --gc removes if not given a size or age
+const DEFAULT_DAYS: u64 = 30;
+
+const DAY: u64 = 24*60*60;
+
+// the files which make up a program in the bin directory, besides the program
+pub const BIN_EXTENSIONS: &[&str] = &["rs","lock","stamp","build","exe","pdb","dwp"];
+
+pub fn human_size(bytes: u64) -> String {
+    let units = ["bytes","KiB","MiB","GiB","TiB"];
+    let (mut size, mut unit) = (bytes as f64, 0);
+    while size >= 1024.0 && unit+1 < units.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} bytes",bytes)
+    } else {
+        format!("{:.1} {}",size,units[unit])
+    }
+}
+
+// like 500M or 2G (in powers of 1024); a plain number is bytes
+pub fn parse_size(s: &str) -> Option<u64> {
+    let s = s.trim();
+    let (num,unit) = match s.find(|c: char| c.is_alphabetic()) {
+        Some(i) => (&s[0..i], s[i..].to_lowercase()),
+        None => (s, String::new())
+    };
+    let scale: u64 = match unit.trim_end_matches("ib").trim_end_matches('b') {
+        "" => 1,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        "t" => 1 << 40,
+        _ => return None
+    };
+    let num: f64 = num.trim().parse().ok()?;
+    if num < 0.0 {
+        return None;
+    }
+    Some((num * scale as f64) as u64)
+}
+
+// like 30d, 12h or 2w; a plain number is days
+pub fn parse_age(s: &str) -> Option<Duration> {
+    let s = s.trim();
+    let (num,unit) = match s.find(|c: char| c.is_alphabetic()) {
+        Some(i) => (&s[0..i], &s[i..]),
+        None => (s, "d")
+    };
+    let scale = match unit {
+        "m" => 60,
+        "h" => 60*60,
+        "d" => DAY,
+        "w" => 7*DAY,
+        _ => return None
+    };
+    let num: u64 = num.trim().parse().ok()?;
+    Some(Duration::from_secs(num * scale))
+}
+
+fn size_of(path: &Path) -> u64 {
+    if path.is_dir() {
+        cache::dir_size(path)
+    } else {
+        fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+    }
+}
+
+// when a file was last read or written; for a directory, the newest
+// time of anything in it
+fn last_used(path: &Path) -> SystemTime {
+    let meta = match fs::metadata(path) {
+        Ok(meta) => meta,
+        Err(_) => return SystemTime::UNIX_EPOCH
+    };
+    let mut used = meta.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+    if let Ok(accessed) = meta.accessed() {
+        used = used.max(accessed);
+    }
+    if meta.is_dir() {
+        if let Ok(entries) = fs::read_dir(path) {
+            for entry in entries.flatten() {
+                used = used.max(last_used(&entry.path()));
+            }
+        }
+    }
+    used
+}
+
+// something --gc can remove, like a program with its generated source
+struct Artifact {
+    name: PathBuf,
+    files: Vec<PathBuf>,
+    size: u64,
+    used: SystemTime,
+}
+
+impl Artifact {
+    fn new(name: PathBuf, files: Vec<PathBuf>) -> Artifact {
+        let size = files.iter().map(|f| size_of(f)).sum();
+        let used = files.iter().map(|f| last_used(f)).max().unwrap_or(SystemTime::UNIX_EPOCH);
+        Artifact { name, files, size, used }
+    }
+
+    fn days_unused(&self, now: SystemTime) -> u64 {
+        now.duration_since(self.used).map(|d| d.as_secs() / DAY).unwrap_or(0)
+    }
+}
+
+fn entries(dir: &Path) -> Vec<PathBuf> {
+    fs::read_dir(dir).map(|entries| entries.flatten().map(|e| e.path()).collect()).unwrap_or_default()
+}
+
+// programs in the bin directory go with their source, lock and debug files
+fn programs(bin: &Path) -> Vec<Artifact> {
+    let mut programs: BTreeMap<String,Vec<PathBuf>> = BTreeMap::new();
+    for path in entries(bin) {
+        let name = match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) if BIN_EXTENSIONS.contains(&ext) => path.file_stem(),
+            _ => path.file_name()
+        };
+        let name = name.and_then(|n| n.to_str()).unwrap_or_default().to_string();
+        programs.entry(name).or_default().push(path);
+    }
+    programs.into_iter()
+        .map(|(name,files)| Artifact::new(bin.join(name), files))
+        .collect()
+}
+
+// everything which can be removed, least recently used first
+fn artifacts() -> Vec<Artifact> {
+    let home = cache::runner_directory();
+    let mut res = programs(&home.join("bin"));
+    let mut dirs = vec![home.join("incremental"), home.join("compare"), home.join("link-plans"), home.join("store")];
+    for profile in &["debug","release"] {
+        dirs.push(home.join("local-deps").join(profile));
+        dirs.push(cache::static_cache_dir().join("target").join(profile).join("incremental"));
+    }
+    for dir in dirs {
+        res.extend(entries(&dir).into_iter().map(|path| Artifact::new(path.clone(), vec![path])));
+    }
+    res.sort_by_key(|a| a.used);
+    res
+}
+
+pub fn cache_stats() {
+    let home = cache::runner_directory();
+    let static_cache = cache::static_cache_dir();
+    let parts = [
+        ("static cache", static_cache.join("target")),
+        ("dynamic cache", cache::dynamic_cache_dir()),
+        ("programs", home.join("bin")),
+        ("program store", home.join("store")),
+        ("incremental", home.join("incremental")),
+        ("local crates", home.join("local-deps")),
+        ("compare builds", home.join("compare")),
+    ];
+    for (name,dir) in &parts {
+        if dir.is_dir() {
+            println!("{:<16}{:>12}  {}",name,human_size(cache::dir_size(dir)),dir.display());
+        }
+    }
+    println!("{:<16}{:>12}  {}","total",human_size(cache::dir_size(&home)),home.display());
+    let now = SystemTime::now();
+    let old: Vec<_> = artifacts().into_iter().filter(|a| a.days_unused(now) >= DEFAULT_DAYS).collect();
+    if ! old.is_empty() {
+        let size: u64 = old.iter().map(|a| a.size).sum();
+        println!("{} not used for {} days ('runner --gc' removes it)",human_size(size),DEFAULT_DAYS);
+    }
+}
+
+fn remove(a: &Artifact, why: &str) {
+    if logging::dry_run() {
+        println!("# remove {} ({}, {})",a.name.display(),human_size(a.size),why);
+        return;
+    }
+    verbose!("removing {} ({})",a.name.display(),why);
+    for file in &a.files {
+        let res = if file.is_dir() {
+            fs::remove_dir_all(file)
+        } else {
+            fs::remove_file(file)
+        };
+        if let Err(e) = res {
+            info!("cannot remove {}: {}",file.display(),e);
+        }
+    }
+}
+
+fn report(count: usize, freed: u64) {
+    let verb = if logging::dry_run() {"would free"} else {"freed"};
+    info!("{} {} from {} item{}",verb,human_size(freed),count,if count == 1 {""} else {"s"});
+}
+
+// remove what hasn't been used for older_than, and then the least recently
+// used until the runner directory is no bigger than max_size
+pub fn gc(max_size: Option<u64>, older_than: Option<Duration>) {
+    let older_than = if max_size.is_none() && older_than.is_none() {
+        Some(Duration::from_secs(DEFAULT_DAYS*DAY))
+    } else {
+        older_than
+    };
+    let home = cache::runner_directory();
+    let now = SystemTime::now();
+    let mut total = cache::dir_size(&home);
+    let (mut count, mut freed) = (0,0);
+    for a in artifacts() {
+        let old = older_than.is_some_and(|age| now.duration_since(a.used).is_ok_and(|d| d > age));
+        let too_big = max_size.is_some_and(|max| total > max);
+        // everything after this was used more recently
+        if ! (old || too_big) {
+            break;
+        }
+        remove(&a, &format!("last used {} days ago",a.days_unused(now)));
+        count += 1;
+        freed += a.size;
+        total = total.saturating_sub(a.size);
+    }
+    report(count, freed);
+    if let Some(max) = max_size {
+        if total > max {
+            info!("the runner directory is still {}: the rest is the caches (see --cache-stats)",human_size(total));
+        }
+    }
+}
+
+// programs whose source file has gone, or which haven't been run for older_than
+pub fn clean_bin(older_than: Option<Duration>) {
+    let older_than = older_than.unwrap_or(Duration::from_secs(DEFAULT_DAYS*DAY));
+    let now = SystemTime::now();
+    let (mut count, mut freed) = (0,0);
+    for a in programs(&cache::runner_directory().join("bin")) {
+        let why = match cache::recorded_source(&a.name) {
+            Some(source) if ! source.exists() => format!("{} is gone",source.display()),
+            _ if now.duration_since(a.used).is_ok_and(|d| d > older_than) =>
+                format!("not run for {} days",a.days_unused(now)),
+            _ => continue
+        };
+        remove(&a, &why);
+        count += 1;
+        freed += a.size;
+    }
+    report(count, freed);
+}
+
+// a program in the bin directory, with its source and other files
+pub fn remove_program(program: &Path) {
+    let bin = cache::runner_directory().join("bin");
+    let name = program.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let name = bin.join(name.strip_suffix(EXE_SUFFIX).unwrap_or(name));
+    if let Some(a) = programs(&bin).into_iter().find(|a| a.name == name) {
+        remove(&a, "asked for");
+    }
+}
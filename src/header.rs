@@ -0,0 +1,94 @@
+// the '//:' lines at the top of a file. Mostly these are runner flags,
+// like '//: -s -x regex', but some are directives:
+//
+//    //: include utils.rs      splice in another file, found next to this one
+//    //: dep mylib=../mylib     link against a local crate (the name is optional)
+//    //: env RUST_LOG=debug      set a variable for the program (like --env)
+//    //: features serde/derive  the static cache's crates must have these features
+//    //: edition 2024           the edition (like -E)
+//    //: toolchain nightly      build and run with this rustup toolchain
+//    //: entry start            run this function rather than the snippet's statements
+use es::traits::*;
+use std::fs;
+use std::path::{Path,PathBuf};
+
+#[derive(Default)]
+pub struct Header {
+    pub flags: Vec<String>,
+    pub includes: Vec<PathBuf>,
+    // the crate name, if given, and its directory
+    pub deps: Vec<(Option<String>,PathBuf)>,
+    pub env: Vec<String>,
+    // the crate and its feature
+    pub features: Vec<(String,String)>,
+    pub edition: Option<String>,
+    pub toolchain: Option<String>,
+    pub entry: Option<String>,
+}
+
+impl Header {
+    pub fn parse(file: &Path, contents: &str) -> Header {
+        let dir = file.parent().unwrap_or_else(|| Path::new(""));
+        let mut header = Header::default();
+        let mut lines = contents.lines().peekable();
+        // skipping any '#!' line of a script
+        if lines.peek().is_some_and(|l| l.starts_with("#!") && ! l.starts_with("#![")) {
+            lines.next();
+        }
+        for line in lines {
+            let rest = match line.strip_prefix("//: ") {
+                Some(rest) => rest.trim(),
+                None => break
+            };
+            if let Some(name) = rest.strip_prefix("include ") {
+                header.includes.push(dir.join(name.trim()));
+            } else if let Some(dep) = rest.strip_prefix("dep ") {
+                let dep = dep.trim();
+                header.deps.push(match dep.split_once('=') {
+                    Some((name,path)) => (Some(name.trim().to_string()), dir.join(path.trim())),
+                    None => (None, dir.join(dep))
+                });
+            } else if let Some(vars) = rest.strip_prefix("env ") {
+                header.env.extend(shlex::split(vars).or_die("bad '//: env' line"));
+            } else if let Some(features) = rest.strip_prefix("features ") {
+                for f in features.split(|c: char| c == ',' || c.is_whitespace()).filter(|f| ! f.is_empty()) {
+                    match f.split_once('/') {
+                        Some((name,feature)) => header.features.push((name.to_string(),feature.to_string())),
+                        None => es::quit(&format!("'//: features' needs crate/feature, like serde/derive, not '{}'",f))
+                    }
+                }
+            } else if let Some(edition) = rest.strip_prefix("edition ") {
+                header.edition = Some(edition.trim().to_string());
+            } else if let Some(entry) = rest.strip_prefix("entry ") {
+                header.entry = Some(entry.trim().to_string());
+            } else if let Some(toolchain) = rest.strip_prefix("toolchain ") {
+                header.toolchain = Some(toolchain.trim().to_string());
+            } else {
+                header.flags.extend(shlex::split(rest).or_die("bad comment args"));
+            }
+        }
+        header
+    }
+
+    // the flags, with those the directives stand for
+    pub fn all_flags(&self) -> Vec<String> {
+        let mut flags = self.flags.clone();
+        for var in &self.env {
+            flags.extend(["--env".to_string(), var.clone()]);
+        }
+        if let Some(edition) = &self.edition {
+            flags.extend(["--edition".to_string(), edition.clone()]);
+        }
+        flags
+    }
+
+    // the included files, one after the other
+    pub fn included(&self) -> String {
+        let mut res = String::new();
+        for file in &self.includes {
+            res += &fs::read_to_string(file).or_then_die(|e| format!("cannot include {}: {}",file.display(),e));
+            res.push('\n');
+        }
+        res
+    }
+}
@@ -0,0 +1,63 @@
+// runner --info describes runner's setup: where things are, how big the caches
+// are and which rustc built them. '--format json' is for scripts and bug reports.
+use std::fs;
+use std::path::Path;
+use json::JsonValue;
+
+use crate::cache;
+use crate::config;
+use crate::platform;
+
+fn mib(bytes: u64) -> String {
+    format!("{:.1} MiB",bytes as f64 / (1024.0 * 1024.0))
+}
+
+fn cache_info(dir: &Path, rustc: Option<String>) -> JsonValue {
+    let mut info = JsonValue::new_object();
+    info["path"] = dir.display().to_string().into();
+    info["exists"] = dir.is_dir().into();
+    info["size"] = cache::dir_size(dir).into();
+    info["rustc"] = rustc.into();
+    info
+}
+
+pub fn info(version: &str, json: bool) {
+    let rustc = es::shell("rustc --version");
+    let config_file = config::config_file();
+    let dynamic = cache::dynamic_cache_dir();
+    let dynamic_rustc = fs::read_to_string(dynamic.join(cache::RUSTC_VERSION)).ok().map(|s| s.trim().to_string());
+
+    let mut doc = JsonValue::new_object();
+    doc["version"] = version.into();
+    doc["runner_directory"] = cache::runner_directory().display().to_string().into();
+    doc["config_file"] = config_file.display().to_string().into();
+    doc["config_file_exists"] = config_file.is_file().into();
+    doc["editor"] = platform::editor().into();
+    doc["rustc"] = rustc.into();
+    doc["static_cache"] = cache_info(&cache::static_cache_dir(), cache::static_cache_rustc());
+    doc["dynamic_cache"] = cache_info(&dynamic, dynamic_rustc);
+    let mut editions = JsonValue::new_object();
+    for (name,edition) in cache::dynamic_editions() {
+        editions[name] = edition.into();
+    }
+    doc["dynamic_cache"]["editions"] = editions;
+    if json {
+        println!("{}",doc.pretty(2));
+        return;
+    }
+    println!("runner {}",doc["version"]);
+    println!("{:<18}{}","runner directory",doc["runner_directory"]);
+    let present = if config_file.is_file() {""} else {" (not present)"};
+    println!("{:<18}{}{}","config file",doc["config_file"],present);
+    println!("{:<18}{}","editor",doc["editor"]);
+    println!("{:<18}{}","rustc",doc["rustc"]);
+    for (name,key) in &[("static cache","static_cache"),("dynamic cache","dynamic_cache")] {
+        let c = &doc[*key];
+        if c["exists"].as_bool() == Some(true) {
+            let built = c["rustc"].as_str().map(|r| format!(", built with {}",r)).unwrap_or_default();
+            println!("{:<18}{} ({}{})",name,c["path"],mib(c["size"].as_u64().unwrap_or(0)),built);
+        } else {
+            println!("{:<18}{} (not present)",name,c["path"]);
+        }
+    }
+}
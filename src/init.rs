@@ -0,0 +1,50 @@
+// runner --init sets up the current directory for snippets: an env.rs explaining
+// what can go in it, and a snippet to try. With --local, the directory also gets
+// its own runner directory (.runner), which is used whenever runner finds this env.rs.
+use es::traits::*;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+use crate::cache;
+
+const ENV_RS: &str = r#"// env.rs - added to the prelude of every snippet in this directory and below.
+// Default flags for runner go in '//:' lines at the very top, for example:
+//
+//   //: -s -x regex          build statically, with the regex crate
+//   //: -O --edition 2021    optimized, using the 2021 edition
+//   //: include helpers.rs   splice in helpers.rs from this directory
+//
+// Anything else here (use statements, helper functions) is shared by all snippets.
+"#;
+
+const HELLO_RS: &str = r#"// try 'runner hello.rs' or 'runner hello.rs dolly'
+let name = args.get(1).map(|s| s.as_str()).unwrap_or("world");
+println!("hello {}", name);
+"#;
+
+fn create(path: &Path, contents: &str) {
+    if path.exists() {
+        info!("{} already exists", path.display());
+    } else {
+        fs::write(path, contents).or_then_die(|e| format!("cannot write {}: {}", path.display(), e));
+        info!("created {}", path.display());
+    }
+}
+
+pub fn init(local: bool) {
+    let here = env::current_dir().or_die("cannot get current directory");
+    create(&here.join("env.rs"), ENV_RS);
+    create(&here.join("hello.rs"), HELLO_RS);
+    if local {
+        let home = here.join(".runner");
+        if home.exists() {
+            info!("{} already exists", home.display());
+        } else {
+            // get_prelude sets up a new runner directory
+            env::set_var("RUNNER_HOME", &home);
+            cache::get_prelude();
+            info!("created {} (add it to .gitignore)", home.display());
+        }
+    }
+}
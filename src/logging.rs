@@ -0,0 +1,77 @@
+// runner's own messages go to stderr, depending on the verbosity level:
+//   0  --quiet: nothing but errors
+//   1  normal: important things like 'building crate'
+//   2  -v: describe what's happening
+//   3  -vv: also the exact commands being run
+// With --dry-run, commands are shown on stdout instead of being run.
+use std::sync::atomic::{AtomicBool,AtomicUsize,Ordering};
+use std::process::Command;
+
+pub const QUIET: usize = 0;
+pub const NORMAL: usize = 1;
+pub const VERBOSE: usize = 2;
+pub const TRACE: usize = 3;
+
+static LEVEL: AtomicUsize = AtomicUsize::new(NORMAL);
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+pub fn set_level(level: usize) {
+    LEVEL.store(level, Ordering::Relaxed);
+}
+
+pub fn enabled(level: usize) -> bool {
+    LEVEL.load(Ordering::Relaxed) >= level
+}
+
+pub fn set_dry_run(dry_run: bool) {
+    DRY_RUN.store(dry_run, Ordering::Relaxed);
+}
+
+pub fn dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+// a command as it could be typed into a shell, with any changes to
+// the environment and directory
+pub fn shell_command(cmd: &Command) -> String {
+    let quote = |s: &std::ffi::OsStr| shlex::quote(&s.to_string_lossy()).to_string();
+    let mut res = Vec::new();
+    if let Some(dir) = cmd.get_current_dir() {
+        res.push(format!("cd {} &&",quote(dir.as_os_str())));
+    }
+    for (k,v) in cmd.get_envs() {
+        match v {
+            Some(v) => res.push(format!("{}={}",k.to_string_lossy(),quote(v))),
+            None => res.push(format!("env -u {}",k.to_string_lossy()))
+        }
+    }
+    res.push(quote(cmd.get_program()));
+    res.extend(cmd.get_args().map(quote));
+    res.join(" ")
+}
+
+// with --dry-run, show the command and say that it has been run
+pub fn dry_run_command(cmd: &Command) -> bool {
+    if dry_run() {
+        println!("{}",shell_command(cmd));
+    }
+    dry_run()
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::NORMAL) { eprintln!($($arg)*); }
+    }
+}
+
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::VERBOSE) { eprintln!($($arg)*); }
+    }
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        if $crate::logging::enabled($crate::logging::TRACE) { eprintln!($($arg)*); }
+    }
+}
@@ -11,10 +11,12 @@ use es::traits::*;
 use std::process;
 use std::env;
 use std::fs;
+use std::io::{self,Read};
 use std::path::{Path,PathBuf};
 use std::collections::{HashSet};
 use std::env::consts::EXE_SUFFIX;
 
+#[macro_use] mod logging;
 mod crate_utils;
 mod platform;
 mod strutil;
@@ -23,11 +25,34 @@ mod cargo_lock;
 mod cache;
 mod state;
 mod compile;
+mod snippets;
+mod config;
+mod diagnostics;
+mod project;
+mod daemon;
+mod batch;
+mod timings;
+mod header;
+mod docs;
+mod init;
+mod doctor;
+mod completions;
+mod info;
+mod snapshot;
+mod verify;
+mod capture;
+mod exec_backend;
+mod compare;
+mod gc;
+mod programs;
+mod vendor;
+mod subcommands;
+mod tui;
 
-use platform::{open,edit};
+use platform::edit;
 use crate_utils::RUSTUP_LIB;
 use state::State;
-use compile::{compile_crate,massage_snippet};
+use compile::{compile_crate,rustc_command,run_rustc,massage_snippet};
 
 const VERSION: &str = "0.4.0";
 
@@ -36,86 +61,907 @@ Compile and run small Rust snippets
   -s, --static build statically (default is dynamic)
   -d, --dynamic overrides --static in env.rs
   -O, --optimize optimized static build
-  -e, --expression evaluate an expression
+  --no-optimize not optimized, even if config.toml says so
+  --opt-level (string) rustc optimization level: 0-3, s or z
+  --lto link-time optimization
+  --codegen-units (integer) fewer units may give faster code
+  --panic (string) panic strategy, abort or unwind
+  --strip (string) strip none, debuginfo or symbols from the program
+  --debuginfo (string) debug information: 0 (none), 1 (line tables) or 2 (full)
+  --split-debuginfo (string) keep debug information apart: off, packed or unpacked
+  --small build as small a program as possible, and show its size (implies -sO)
+  -I, --code-from-stdin read the program (or with -e, -i or --type, the expression) from stdin, as does a program '-'; otherwise stdin is the program's
+  -e, --expression evaluate an expression (another -e gets its value as 'it')
   -i, --iterator iterate over an expression
-  -n, --lines evaluate expression over stdin; the var 'line' is defined
+  --type show the type of an expression, without running it
+  -n, --lines evaluate expression over stdin; the var 'line' is defined, and 'nr' is its number (from 1)
+  --acc (string) with -n, start the var 'acc' with this, like 'HashMap::new()'; it's kept from line to line, and printed at the end if there's no --end
+  -j, --json parse stdin as JSON into the var 'json' (with -n, each line)
+  --csv read stdin as CSV; each record is the var 'rec'
+  -g, --grep (string) evaluate expression for each line of stdin matching this regex (using the regex crate); the var 'caps' has its captures
+  --pipe evaluate an expression with stdin as the var 'input' (a String, without the last newline) and print it with Display (another --pipe gets the value as 'input')
+  --headers with --csv, the first line names the fields, and the var 'row' maps names to values
+  --field-sep (string) with -n, split 'line' into 'fields' with this (default whitespace); the --csv delimiter
+  --begin (string) with -n, -g, -j or --csv, code to run before the first line, like 'let mut total = 0'
+  --end (string) with -n, -g or --csv, code to run after the last line (printed if an expression)
+  --parallel with -n, work on lines in parallel using rayon (output stays in order)
+  --unordered with --parallel, print each result as soon as it's ready
+  --bytes with -n, read stdin as it comes, undecoded: the var 'chunk' is each piece (a &[u8]), and 'out' is stdout for write_all
+  --display print values with Display rather than Debug
+  --output-format (default debug) print values as debug, display or json (using serde_json)
+  --sep (string) with -i, print values on one line separated by this
+  --take (integer) with -i, only the first values
+  --enumerate with -i, print the index before each value
   -x, --extern... (string) add an extern crate to the snippet
   -X, --wild... (string) like -x but implies wildcard import
   -M, --macro... (string) like -x but implies macro import
   -p, --prepend (default '') put this statement in body (useful for -i etc)
+  --raw don't turn single-quoted strings into double-quoted ones on Windows
   -N, --no-prelude do not include runner prelude
   -c, --compile-only  compiles program and copies to output dir
-  -o, --output (path default cargo) change the default output dir for compilation
-  -r, --run  don't compile, only re-run
+  -o, --output (path default cargo) change the default output dir for compilation, or name the program (a dir if it ends with /)
+  --emit-source with -c, also write the generated program next to it as a .rs file
+  --install build with -O --static and put the program in ~/.cargo/bin (or install_dir in config.toml)
+  --install-as (string) the same, under this name
+  --uninstall (string) remove an installed program
+  -r, --run  don't compile, only re-run (a program from --list can be given by name, from anywhere)
+  --list list the compiled programs which can be re-run, with how they were built
+  --fresh don't use incremental compilation for this build
+  --no-run build the program, but don't run it
+  --batch the arguments are more programs: build them all, and then run them in turn
+  --keep-going with several programs, run the others if some fail to build
+  --all with a directory, build and run all the programs in it, as with several programs
+  --plain only the programs' output goes to stdout (the default if it isn't a terminal)
   -S, --no-simplify by default, attempt to simplify rustc error messages
+  --simplify simplify them, even if config.toml says not to
+  --color (default auto) colour rustc errors: auto, always or never
+  --message-format (default human) rustc errors as human text or json
+  --error-format (default human) 'short' gives one file:line:col line for each error
+  --explain explain the first error code if compilation fails
+  --timings show how long runner, rustc and the program took
+  --timings-json the same as JSON
+  --repeat (integer) run the program this many times and show how long it took
+  --warmup (integer default 0) with --repeat, runs first which aren't timed
+  --compare build two programs optimized, run them in turn (--repeat times, default 10) and compare their speed
+  --snapshot compare the program's output with the .snap file next to it (made the first time)
+  --snapshot-update the same, but make the output the new snapshot if it differs
+  --verify check the program's output against the '//=> expected' comments in it
+  --expect-status (integer) succeed only if the program exits with this status (runner exits with 1 if it doesn't)
+  --expect-fail succeed only if the program fails
+  --capture capture the program's output and errors, and print a JSON report with them, its exit status and how long it took
+  --script run as a script: no messages from runner, only rebuilt when it changes, and all arguments go to the program (implied by a '#!' line naming runner)
+  --dry-run show the commands which would build and run the program, without running them
+  --show-code show the generated program before building it
+  --show-code-only print the generated program, and do nothing else
+  --emit-project (string) write the generated program as a Cargo project in this directory
   -E, --edition (default '2018') Rust edition
+  --unstable... (string) pass a -Z option to a nightly rustc, like polonius or print-type-sizes
+  --rpath dynamic programs find their libraries without a library path (as macOS needs for protected programs)
+  --compiler-wrapper (string) run rustc through this, like sccache (also RUSTC_WRAPPER)
+  --no-env don't look for env.rs in this directory or its parents
+  --env... (string) set an environment variable for the program, like KEY=VALUE
+  --dotenv set the program's environment variables from .env in this directory
+  --chdir (string) run the program in this directory
+  --docker (string) build statically, and run the program in a container from this image, with the current directory mounted read-only
+  --out-file (string) write the program's output to this file
+  --err-file (string) write the program's errors to this file
+  --append add to --out-file and --err-file, rather than replacing them
+  --nice (integer) run the program at this priority, from -20 (highest) to 19 (lowest)
+  --cpus (string) run the program only on these CPUs, like 0,2-3 (Linux)
+  --sysroot (string) the Rust toolchain to find the standard library in, if not the one rustc reports (also RUNNER_SYSROOT)
+  --home (string) runner directory to use instead of ~/.cargo/.runner (also RUNNER_HOME)
+  --init create env.rs and a sample snippet in this directory
+  --local with --init, give this directory its own runner directory (.runner)
+  --doctor check that runner has what it needs, and suggest fixes
+  --info show runner's version, directories, caches and settings
+  --format (default text) with --info, text or json
+  --completions (string) write a completion script for bash, zsh, fish or powershell
+  --crate-names list the crates in the static cache and the aliases (used by completion)
+
+  Snippet Library:
+  --save (string) save the expression or file as a named snippet
+  --tag (string...) tags for a saved snippet, e.g. 'parsing,csv'
+  --load (string) run a named snippet from the library
+  --paste run the code on the clipboard
+  --list-snippets list the snippets in the library
+  --search (string) search snippet names, tags and source
 
   Cache Management:
-  --add  (string...) add new crates to the cache
-  --update update all, or a specific package given as argument
-  --edit  edit the static cache Cargo.toml
+  --add  (string...) add new crates to the cache, like 'regex', 'serde@1.0.200' or 'regex@^1.10'
+  --auto-add add any crates missing from the static cache without asking
+  --registry (string) with --add, get crates from this registry (one named in ~/.cargo/config.toml) rather than crates.io
+  --update update all, or a specific package given as argument (with --dry-run, show what would change)
+  --remove (string) take a crate out of the static cache, and rebuild it
+  --unpin with --update, release crates added with a version like 'serde@1.0.200' (all, or the package)
+  --edit  edit the static cache Cargo.toml (with -P, the crate's source)
   --build rebuild the static cache
+  --jobs (integer) how many jobs cargo runs at once when building the static cache (also, anything after '--' with --build or --add goes to cargo)
   --cleanup clean out stale rlibs from cache
+  --vendor (path) copy the sources of the static cache's crates to this directory, and build from them (offline)
+  --export-cache (path) write the static cache to a tarball like cache.tar.gz, without its build
+  --import-cache (path) replace the static cache with one from --export-cache, and build it
+  --cache-stats show how much disk the caches and compiled programs take
+  --gc remove compiled programs and build state, least recently used first (by default, what's unused for 30 days)
+  --max-size (string) with --gc, remove until the runner directory is no bigger than this, like 2G or 500M
+  --older-than (string) with --gc, remove what hasn't been used for this long, like 30d, 12h or 2w
+  --clean-bin remove compiled programs whose source file has gone, or which haven't been run for --older-than (default 30d)
   --crates current crates and their versions in cache
+  --tui browse the static cache's crates, the snippets and the compiled programs, and act on them (Unix only)
+  --why (string) show which crates in the static cache need this one
   --doc  display documentation (any argument will be specific crate name)
   --edit-prelude edit the default prelude for snippets
-  --alias (string...) crate aliases in form alias=crate_name (used with -x)
+  --edit-config edit config.toml, which has defaults for runner flags
+  --alias (string...) crate aliases in form alias=crate_name (used with -x), or alias='-x a -x b' for several flags
+  --alias-list list the aliases
+  --alias-remove (string) remove an alias
 
   Dynamic compilation:
   -P, --crate-path show path of crate source in Cargo cache
   -C, --compile  compile crate dynamically (limited)
+  --search-cargo-cache with -P or -C, also look for crates Cargo has downloaded for other projects
   -L, --link (string) path for extra libraries
   --cfg... (string) pass configuration variables to rustc
   --features (string...) enable features in compilation
   --libc  link dynamically against libc (special case)
   (--extern is used to explicitly link in a crate by name)
 
-  -v, --verbose describe what's happening
+  Commands, which come first (the flags work as well):
+  runner run FILE ARGS        the same as 'runner FILE ARGS'
+  runner eval EXPR            -e EXPR
+  runner cache add CRATES     --add CRATES; also cache remove, build, update, list (--crates),
+                              why, edit, clean (--cleanup), stats, gc, vendor, export and import
+  runner crate path NAME      -P NAME; also crate compile (-C)
+  runner doc NAME             --doc NAME
+
+  --daemon keep a runner in the background to do the work (Unix only)
+  --stop-daemon stop the background runner
+  --no-daemon don't use the background runner
+
+  -v, --verbose describe what's happening (-vv shows commands as well)
+  -q, --quiet only show errors from runner itself
   -V, --version version of runner
 
   <program> (string) Rust program, snippet or expression
   <args> (string...) arguments to pass to program
 ";
 
-fn read_file_with_arg_comment(args: &mut lapp::Args, file: &Path) -> (String,bool) {
+// default flags, with where they came from
+type Layer = (String,Vec<String>);
+
+const EDITIONS: &[&str] = &["2015","2018","2021","2024"];
+
+// the contents, any default flags from first lines like '//: -s', and the
+// header's directives, like '//: include utils.rs'
+fn read_file_with_header(file: &Path) -> (String,Option<Layer>,header::Header) {
     let contents = fs::read_to_string(file).or_die("cannot read file");
-    let first_line = contents.lines().next().or_die("empty file");
-    let arg_comment = "//: ";
-    let has_arg_comment = first_line.starts_with(arg_comment);
-    if has_arg_comment {
-        let default_args = &first_line[arg_comment.len()..];
-        let default_args = shlex::split(default_args).or_die("bad comment args");
-        args.parse_command_line(default_args).or_die("cannot parse comment args");
-        args.clear_used();
+    if contents.is_empty() {
+        es::quit("empty file");
     }
-    (contents,has_arg_comment)
+    let header = header::Header::parse(file, &contents);
+    let flags = header.all_flags();
+    let layer = if flags.is_empty() {
+        None
+    } else {
+        Some((file.display().to_string(), flags))
+    };
+    (contents,layer,header)
+}
 
+// where --install puts programs. 'cargo' means ~/.cargo/bin, as with --output
+fn install_dir() -> PathBuf {
+    match config::CONFIG.install_dir.as_deref() {
+        Some(dir) => match dir.strip_prefix("~/") {
+            Some(rest) => dirs::home_dir().or_die("no home!").join(rest),
+            None => PathBuf::from(dir)
+        },
+        None => PathBuf::from("cargo")
+    }
 }
 
-fn main() {
+// layers of default flags are parsed in order, and then the command line.
+// Later flags override earlier ones, except multiple flags like --extern which add up
+fn parse_args(layers: &[Layer], command_line: &[String]) -> lapp::Args<'static> {
     let mut args = lapp::Args::new(USAGE);
     args.parse_spec().or_die("bad spec");
-    let env = Path::new("env.rs");
-    let env_prelude = if env.exists() {
-        let (contents,_) = read_file_with_arg_comment(&mut args, env);
-        Some(contents)
+    for (source,flags) in layers {
+        args.parse_command_line(cache::expand_bundles(flags.clone())).or_then_die(|e| format!("bad flags in {}: {}",source,e));
+        args.clear_used();
+    }
+    args.parse_command_line(cache::expand_bundles(command_line.to_vec())).or_die("bad command line");
+    args
+}
+
+// does a config layer or the command line set the edition? ('-sE 2021' counts too)
+fn gives_edition(flags: &[String]) -> bool {
+    flags.iter().take_while(|f| *f != "--").any(|f| {
+        f == "--edition" || f.starts_with("--edition=") ||
+            (f.starts_with('-') && ! f.starts_with("--") && f[1..].chars().all(|c| c.is_ascii_alphabetic()) && f.contains('E'))
+    })
+}
+
+// the command line, taken apart
+struct CommandLine {
+    // up to any '--', except that '-vv' (even more verbose) must become '-v' for lapp
+    args: Vec<String>,
+    // after '--' (or a '#!' script), which go straight to the program
+    passthrough: Vec<String>,
+    // the expressions after a second or later '-e' or '--pipe', which get the previous value
+    stages: Vec<String>,
+    // the verbosity asked for with '-vv'
+    extra_verbosity: usize,
+}
+
+// a file starting with a line like '#!/usr/bin/env runner'
+fn is_shebang_script(arg: &str) -> bool {
+    use std::io::BufRead;
+    if arg.starts_with('-') || ! Path::new(arg).is_file() {
+        return false;
+    }
+    let mut first = String::new();
+    match fs::File::open(arg) {
+        Ok(f) => std::io::BufReader::new(f).read_line(&mut first).is_ok()
+            && first.starts_with("#!") && first.contains("runner"),
+        Err(_) => false
+    }
+}
+
+// a flag which takes a value, like '--sep' or '-o', or a group of short flags ending with one
+fn takes_value(value_flags: &[String], arg: &str) -> bool {
+    if arg.starts_with("--") {
+        value_flags.iter().any(|f| f == arg)
+    } else if arg.len() > 1 && arg.starts_with('-') && arg.is_ascii() {
+        let last = format!("-{}",arg.chars().last().unwrap());
+        value_flags.contains(&last)
+    } else {
+        false
+    }
+}
+
+fn command_line() -> CommandLine {
+    let mut extra_verbosity = 0;
+    let mut expressions = 0;
+    let mut runner_args = Vec::new();
+    let mut stages = Vec::new();
+    let mut args: Vec<String> = daemon::command_line_args().into_iter().skip(1).collect();
+    // when run as a script, everything after it belongs to the script, even '--'
+    let explicit = args.iter().take_while(|a| *a != "--").any(|a| a == "--script");
+    let value_flags = completions::value_flags(USAGE);
+    // but not a flag's value, as in '--err-file log.txt'
+    let mut after_flag = false;
+    let script = args.iter().take_while(|a| *a != "--").position(|a| {
+        let script = ! after_flag && (is_shebang_script(a) || (explicit && ! a.starts_with('-') && Path::new(a).is_file()));
+        after_flag = takes_value(&value_flags, a);
+        script
+    });
+    let mut passthrough = if let Some(i) = script {
+        if ! explicit {
+            runner_args.push("--script".to_string());
+        }
+        args.split_off(i+1)
+    } else if let Some(i) = args.iter().position(|a| a == "--") {
+        let rest = args.split_off(i+1);
+        args.pop();
+        rest
+    } else {
+        Vec::new()
+    };
+    let mut after_flag = false;
+    let mut positionals = 0;
+    let mut before = args.into_iter();
+    while let Some(a) = before.next() {
+        // a program '-' means the code comes from stdin, as with -I (but not in '--sep -',
+        // or as an argument for the program)
+        if ! after_flag && (a == "-" || ! a.starts_with('-')) {
+            positionals += 1;
+            if a == "-" && positionals == 1 {
+                runner_args.push("--code-from-stdin".to_string());
+                continue;
+            }
+            if a == "-" {
+                // lapp would drop it, so it and what follows go straight to the program
+                let mut rest = vec![a];
+                rest.extend(before.by_ref());
+                rest.append(&mut passthrough);
+                passthrough = rest;
+                break;
+            }
+        }
+        after_flag = takes_value(&value_flags, &a);
+        let is_short = a.len() > 2 && a.starts_with('-') && a[1..].chars().all(|c| c.is_ascii_alphabetic());
+        let stage = a == "-e" || a == "--expression" || a == "--pipe";
+        if stage || (is_short && a.ends_with('e')) {
+            // like '-e' or '-se'
+            expressions += 1;
+            if expressions > 1 && stage {
+                stages.extend(before.next());
+                continue;
+            }
+        }
+        if is_short && a[1..].chars().all(|c| c == 'v') {
+            extra_verbosity = a.len() - 2;
+            runner_args.push("-v".to_string());
+        } else {
+            runner_args.push(a);
+        }
+    }
+    CommandLine { args: runner_args, passthrough, stages, extra_verbosity }
+}
+
+// a few flags must be known before the command line is parsed properly
+fn early_switch(name: &str) -> bool {
+    let flag = format!("--{}",name);
+    daemon::command_line_args().into_iter().skip(1).take_while(|a| a != "--").any(|a| a == flag)
+}
+
+// like early_switch, but for flags with values like '--home DIR'
+fn early_flag(name: &str) -> Option<String> {
+    let flag = format!("--{}",name);
+    let mut iter = daemon::command_line_args().into_iter().skip(1).take_while(|a| a != "--");
+    while let Some(arg) = iter.next() {
+        if arg == flag {
+            return Some(iter.next().unwrap_or_default());
+        }
+        if let Some(value) = arg.strip_prefix(&format!("{}=",flag)) {
+            return Some(value.into());
+        }
+    }
+    None
+}
+
+// look for env.rs in this directory and its parents, stopping at
+// the root of a repo or the home directory. Outermost come first.
+fn find_env_files() -> Vec<PathBuf> {
+    let home = dirs::home_dir();
+    let mut res = Vec::new();
+    let mut dir = env::current_dir().or_die("cannot get current directory");
+    loop {
+        let env = dir.join("env.rs");
+        if env.is_file() {
+            res.push(env);
+        }
+        if dir.join(".git").exists() || Some(&dir) == home.as_ref() || ! dir.pop() {
+            break;
+        }
+    }
+    res.reverse();
+    res
+}
+
+// with '//: toolchain nightly', runner starts again under that toolchain, so that
+// rustc and the sysroot are its own. The daemon is not used, since it found its
+// sysroot with the default toolchain
+fn run_with_toolchain(toolchain: &str) -> ! {
+    let exe = env::current_exe().or_die("cannot find runner");
+    let mut builder = process::Command::new("rustup");
+    builder.args(["run",toolchain]).arg(exe).arg("--no-daemon")
+        .args(daemon::command_line_args().into_iter().skip(1))
+        .env("RUNNER_TOOLCHAIN", toolchain);
+    trace!("running {:?}",builder);
+    let status = builder.status().or_then_die(|e| format!("'//: toolchain' needs rustup: {}",e));
+    process::exit(platform::exit_code(&status));
+}
+
+// the crates named in '//: features' must be in the static cache with those features
+fn check_features(features: &[(String,String)]) {
+    let m = cache::get_metadata();
+    for (name,feature) in features {
+        let e = m.get_meta_entry(name)
+            .unwrap_or_else(|| es::quit(&format!("{} is not in the static cache: 'runner --add {}'",name,name)));
+        if ! e.features.split_whitespace().any(|f| f == feature) {
+            es::quit(&format!("{} in the static cache doesn't have the feature '{}': add it to its line with 'runner --edit' (like features=[\"{}\"]), then 'runner --build'",
+                name,feature,feature));
+        }
+    }
+}
+
+// KEY=VALUE lines, as in a .env file. Blank lines and '#' comments are ignored,
+// as is 'export' in front; values may be quoted
+fn read_dotenv(file: &Path) -> Vec<(String,String)> {
+    let contents = fs::read_to_string(file).or_then_die(|e| format!("cannot read {}: {}",file.display(),e));
+    let mut res = Vec::new();
+    for (i,line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let (key,value) = line.split_once('=')
+            .or_then_die(|_| format!("{}:{}: expecting KEY=VALUE",file.display(),i+1));
+        let value = value.trim();
+        let unquoted = ['"','\''].iter().find_map(|&q| value.strip_prefix(q)?.strip_suffix(q));
+        res.push((key.trim().to_string(), unquoted.unwrap_or(value).to_string()));
+    }
+    res
+}
+
+// the arguments for println! and friends which show a value, as asked
+// for by --output-format: debug, display or json
+fn format_args(fmt: &str, val: &str) -> String {
+    match fmt {
+        "display" => format!("\"{{}}\",{}",val),
+        "json" => format!("\"{{}}\",serde_json::to_string(&({}))?",val),
+        _ => format!("\"{{:?}}\",{}",val)
+    }
+}
+
+// an expression is printed, unless it's really a statement ending with '}' or ';'
+fn print_code(expr: &str, fmt: &str) -> String {
+    let trimmed = expr.trim_end();
+    if trimmed.ends_with('}') || trimmed.ends_with(';') {
+        format!("{};",trimmed.trim_end_matches(';'))
+    } else {
+        format!("println!({});",format_args(fmt,expr))
+    }
+}
+
+// with --json, the variable 'json' is standard input parsed with serde_json
+fn json_code(args: &lapp::Args, expr: String, fmt: &str) -> String {
+    let begin = args.get_string_result("begin").map(|c| cache::quote(c) + ";").unwrap_or_default();
+    format!("extern crate serde_json;
+{}
+let mut input = String::new();
+io::stdin().read_to_string(&mut input)?;
+let json: serde_json::Value = serde_json::from_str(&input)?;
+{}",begin,print_code(&expr,fmt))
+}
+
+// with -g, the expression is evaluated for each line matching the regex, and
+// 'caps' has what it captured, so 'caps[1]' is the first group
+fn grep_code(args: &lapp::Args, pattern: &str, expr: String, fmt: &str) -> String {
+    use cache::quote;
+    let begin = args.get_string_result("begin").map(|c| quote(c) + ";").unwrap_or_default();
+    let mut s = format!("extern crate regex;
+{}
+let re = regex::Regex::new({:?})?;
+let stdin = io::stdin();
+for line in stdin.lock().lines() {{
+    let line = line?;
+    let caps = match re.captures(&line) {{
+        Some(caps) => caps,
+        None => continue
+    }};
+    {}
+}}",begin,pattern,print_code(&expr,fmt));
+    if let Ok(end) = args.get_string_result("end") {
+        s += "\n";
+        s += &print_code(&quote(end),fmt);
+    }
+    s
+}
+
+// with --pipe, the variable 'input' is standard input without its last newline, so
+// runner can be a step in a shell pipeline. Values are shown with Display, since
+// the next step wants 'hello' and not '"hello"'
+fn pipe_code(pipeline: String, expr: String, fmt: &str) -> String {
+    let fmt = if fmt == "json" {fmt} else {"display"};
+    format!("let mut input = String::new();
+io::stdin().read_to_string(&mut input)?;
+if input.ends_with('\\n') {{
+    input.pop();
+    if input.ends_with('\\r') {{
+        input.pop();
+    }}
+}}
+{}{}",pipeline,print_code(&expr,fmt))
+}
+
+// with --csv, the variable 'rec' is each record of standard input, and with
+// --headers the first line names the fields so that 'row["name"]' works
+fn csv_code(args: &lapp::Args, expr: String, fmt: &str) -> String {
+    let headers = args.get_bool("headers");
+    let mut reader = format!("csv::ReaderBuilder::new().has_headers({})",headers);
+    if let Ok(sep) = args.get_string_result("field-sep") {
+        if sep.len() != 1 {
+            args.quit("--field-sep must be a single character for --csv");
+        }
+        reader += &format!(".delimiter({:?})",sep.as_bytes()[0]);
+    }
+    let row = if headers {
+        "let headers = reader.headers()?.clone();\n"
+    } else {
+        ""
+    };
+    let row_var = if headers {
+        "#[allow(unused_variables)]\n    let row: std::collections::HashMap<&str,&str> = headers.iter().zip(rec.iter()).collect();"
+    } else {
+        ""
+    };
+    let begin = args.get_string_result("begin").map(|c| cache::quote(c) + ";").unwrap_or_default();
+    let mut s = format!("extern crate csv;
+{}
+let mut reader = {}.from_reader(io::stdin());
+{}for rec in reader.records() {{
+    let rec = rec?;
+    {}
+    {}
+}}",begin,reader,row,row_var,print_code(&expr,fmt));
+    if let Ok(end) = args.get_string_result("end") {
+        s += "\n";
+        s += &print_code(&cache::quote(end),fmt);
+    }
+    s
+}
+
+// with --bytes, the variable 'chunk' is each piece of stdin as it is read, not
+// decoded as UTF-8, so binary streams can be processed. Writing to 'out' goes
+// straight to stdout
+fn bytes_code(args: &lapp::Args, expr: String, fmt: &str) -> String {
+    use cache::quote;
+    if args.get_bool("json") || args.get_bool("parallel") || args.get_string_result("field-sep").is_ok() {
+        args.quit("--bytes doesn't go with -j, --parallel or --field-sep, which need lines");
+    }
+    let begin = args.get_string_result("begin").map(|c| quote(c) + ";").unwrap_or_default();
+    let mut s = format!("{}
+let mut stdin = io::stdin().lock();
+#[allow(unused_mut,unused_variables)]
+let mut out = io::stdout().lock();
+let mut buf = vec![0u8; 64*1024];
+loop {{
+    let n = stdin.read(&mut buf)?;
+    if n == 0 {{
+        break;
+    }}
+    let chunk = &buf[..n];
+    {}
+}}",begin,print_code(&expr,fmt));
+    if let Ok(end) = args.get_string_result("end") {
+        s += "\n";
+        s += &print_code(&quote(end),fmt);
+    }
+    s
+}
+
+// The variable 'line' is available to an expression, evaluated for each line in stdin
+// and so is 'fields', like awk's $1, $2... (and 'json', with --json). 'nr' is
+// the line number as in awk, and 'acc' is kept from one line to the next.
+// But if the expression ends with '}' then don't dump out this value!
+fn lines_code(args: &lapp::Args, expr: String, fmt: &str) -> String {
+    use cache::quote;
+    let split = match args.get_string_result("field-sep") {
+        Ok(sep) => format!("split({:?})",sep),
+        Err(_) => "split_whitespace()".into()
+    };
+    let mut header = String::new();
+    let mut vars = format!("#[allow(unused_variables)]\nlet nr = nr + 1;\n#[allow(unused_variables)]\nlet fields: Vec<&str> = line.{}.collect();",split);
+    if args.get_bool("json") {
+        // JSON lines
+        header += "extern crate serde_json;\n";
+        vars += "\nlet json: serde_json::Value = serde_json::from_str(&line)?;";
+    }
+    if args.get_bool("parallel") {
+        header += "extern crate rayon;\nuse rayon::prelude::*;\n";
+    }
+    header += &args.get_string_result("begin").map(|c| quote(c) + ";").unwrap_or_default();
+    let acc = args.get_string_result("acc").ok();
+    if let Some(acc) = &acc {
+        if args.get_bool("parallel") {
+            args.quit("--acc doesn't go with --parallel, since the lines are worked on at the same time");
+        }
+        header += &format!("\nlet mut acc = {};",quote(acc.clone()));
+    }
+    let mut s = if ! args.get_bool("parallel") {
+        format!("{}
+let stdin = io::stdin();
+for (nr,line) in stdin.lock().lines().enumerate() {{
+    let line = line?;
+    {}
+    {}
+}}",header,vars,print_code(&expr,fmt))
+    } else if args.get_bool("unordered") {
+        // each line is printed as soon as it's done
+        format!("{}
+io::BufReader::new(io::stdin()).lines().enumerate().par_bridge().try_for_each(|(nr,line)| -> Result<(),Box<dyn std::error::Error+Send+Sync>> {{
+    let line = line?;
+    {}
+    {}
+    Ok(())
+}})?;",header,vars,print_code(&expr,fmt))
+    } else {
+        // all the lines are read first, so that the results come out in order
+        let trimmed = expr.trim_end();
+        let body = if trimmed.ends_with('}') || trimmed.ends_with(';') {
+            format!("{};\nOk(None)",trimmed.trim_end_matches(';'))
+        } else {
+            format!("Ok(Some(format!({})))", format_args(fmt,&expr))
+        };
+        format!("{}
+let lines: Vec<String> = io::stdin().lock().lines().collect::<Result<_,_>>()?;
+let results = lines.par_iter().enumerate().map(|(nr,line)| -> Result<Option<String>,Box<dyn std::error::Error+Send+Sync>> {{
+    {}
+    {}
+}}).collect::<Result<Vec<_>,_>>()?;
+for res in results.into_iter().flatten() {{
+    println!(\"{{}}\",res);
+}}",header,vars,body)
+    };
+    if let Ok(end) = args.get_string_result("end") {
+        s += "\n";
+        s += &print_code(&quote(end),fmt);
+    } else if acc.is_some() {
+        s += "\n";
+        s += &print_code("acc",fmt);
+    }
+    s
+}
+
+// runner flags which a saved expression snippet needs to build properly
+fn snippet_arg_comment(args: &lapp::Args) -> String {
+    let mut res = Vec::new();
+    if args.get_bool("static") {
+        res.push("-s".to_string());
+    }
+    if args.get_bool("no-prelude") {
+        res.push("-N".to_string());
+    }
+    for (flag,name) in &[("-x","extern"),("-X","wild"),("-M","macro")] {
+        for c in args.get_strings(name) {
+            res.push(format!("{} {}",flag,c));
+        }
+    }
+    let prepend = args.get_string("prepend");
+    if ! prepend.is_empty() {
+        res.push(format!("-p {}",shlex::quote(&prepend)));
+    }
+    res.join(" ")
+}
+
+fn set_verbosity(args: &lapp::Args, extra_verbosity: usize) {
+    logging::set_level(if args.get_bool("quiet") || args.get_bool("script") {
+        logging::QUIET
+    } else if args.get_bool("verbose") {
+        logging::VERBOSE + extra_verbosity
+    } else {
+        logging::NORMAL
+    });
+}
+
+fn main() {
+    // --home affects where everything lives, so it goes into the
+    // environment for runner_directory() (and any runner started by the program)
+    if let Some(home) = early_flag("home") {
+        let home = env::current_dir().or_die("cannot get current directory").join(home);
+        env::set_var("RUNNER_HOME", home);
+    } else if env::var("RUNNER_HOME").is_err() && ! early_switch("no-env") {
+        // made by 'runner --init --local', next to the nearest env.rs
+        let local = find_env_files().last()
+            .and_then(|env| env.parent().map(|dir| dir.join(".runner")))
+            .filter(|home| home.is_dir());
+        if let Some(home) = local {
+            env::set_var("RUNNER_HOME", home);
+        }
+    }
+    // must be known before the sysroot is first needed
+    if let Some(sysroot) = early_flag("sysroot") {
+        env::set_var("RUNNER_SYSROOT", sysroot);
+    }
+    if early_switch("init") {
+        init::init(early_switch("local"));
+        return;
+    }
+    // not in the daemon, which may have been started with another environment
+    if early_switch("doctor") {
+        process::exit(doctor::doctor());
+    }
+    // completion must be quick, so it doesn't need the daemon
+    if let Some(shell) = early_flag("completions") {
+        completions::completions(&shell, USAGE);
+        return;
+    }
+    if early_switch("crate-names") {
+        completions::crate_names();
+        return;
+    }
+    // the TUI needs the terminal, so it isn't run by the daemon
+    if early_switch("tui") {
+        tui::tui();
+        return;
+    }
+    if early_switch("daemon") {
+        daemon::start(run);
+        return;
+    }
+    if early_switch("stop-daemon") {
+        daemon::stop();
+        return;
+    }
+    // editors need the terminal, so they are not run by the daemon
+    let editing = ["edit","edit-prelude","edit-config"].iter().any(|f| early_switch(f));
+    // and the daemon has already found its sysroot. The daemon's client runs the
+    // program, and doesn't know about priority and CPUs
+    let own_process = early_flag("sysroot").is_some() || early_flag("nice").is_some() || early_flag("cpus").is_some();
+    if ! editing && ! early_switch("no-daemon") && ! own_process {
+        if let Some(code) = daemon::forward() {
+            process::exit(code);
+        }
+    }
+    run();
+}
+
+fn run() {
+    timings::start();
+    // defaults from config.toml have lowest precedence
+    let mut layers = vec![("config.toml".to_string(),config::CONFIG.default_args())];
+
+    let env_files = if early_switch("no-env") {
+        Vec::new()
+    } else {
+        find_env_files()
+    };
+    // features and a toolchain may come from env.rs files as well as the program
+    let mut features = Vec::new();
+    let mut toolchain = None;
+    // outer env.rs files come first, so inner ones can override their arg comments
+    let env_prelude = if ! env_files.is_empty() {
+        let mut prelude = String::new();
+        for env in env_files {
+            let (contents,layer,header) = read_file_with_header(&env);
+            layers.extend(layer);
+            features.extend(header.features.iter().cloned());
+            toolchain = header.toolchain.clone().or(toolchain);
+            prelude += &header.included();
+            prelude += &contents;
+            prelude.push('\n');
+        }
+        Some(prelude)
+    } else {
+        None
+    };
+
+    let CommandLine { args: mut command_line, mut passthrough, stages, extra_verbosity } = command_line();
+    let mut args = parse_args(&layers, &command_line);
+    // with --build or --add, anything after '--' is for cargo
+    let cache_build = args.get_bool("build") || ! args.get_strings("add").is_empty();
+    if args.get_string_result("program").is_err() && ! passthrough.is_empty() && ! cache_build {
+        // as in 'runner -e -- -10', the program itself comes after '--'
+        command_line.push("--".into());
+        command_line.push(passthrough.remove(0));
+        args = parse_args(&layers, &command_line);
+    }
+
+    // several programs are built together, and then run in turn
+    if let Ok(program) = args.get_string_result("program") {
+        let others = args.get_strings("args");
+        let all = args.get_bool("all");
+        if all && ! Path::new(&program).is_dir() {
+            args.quit("--all needs a directory of programs");
+        }
+        if all && ! others.is_empty() {
+            args.quit("with --all, arguments for the programs go after '--'");
+        }
+        let compare = args.get_bool("compare") && others.len() == 1;
+        if all || compare || args.get_bool("batch") {
+            set_verbosity(&args, extra_verbosity);
+            let files = if all {
+                batch::programs_in(Path::new(&program))
+            } else {
+                let mut files = vec![program.clone()];
+                files.extend(others);
+                files
+            };
+            if files.is_empty() {
+                args.quit(&format!("no programs in {}",program));
+            }
+            if let Some(file) = files.iter().find(|f| ! all && ! batch::is_program(f)) {
+                args.quit(&format!("{} is not a program",file));
+            }
+            // the flags for each runner are the command line without the programs,
+            // which are picked out by position, since a flag's value can look like one
+            let value_flags = completions::value_flags(USAGE);
+            let mut after_flag = false;
+            let mut flags: Vec<String> = daemon::command_line_args().into_iter().skip(1)
+                .take_while(|a| a != "--")
+                .filter(|a| {
+                    let flag = after_flag || a.starts_with('-');
+                    after_flag = ! after_flag && takes_value(&value_flags, a);
+                    flag && a != "--all" && a != "--batch"
+                })
+                .collect();
+            // their errors are captured, but can still be in colour
+            let color = diagnostics::use_color(&args.get_string("color")).unwrap_or_else(|e| args.quit(&e));
+            if ! flags.iter().any(|f| f.starts_with("--color")) && color {
+                flags.extend(["--color".to_string(),"always".to_string()]);
+            }
+            if args.get_bool("compare") {
+                if files.len() != 2 {
+                    args.quit("--compare needs two programs");
+                }
+                flags.retain(|f| f != "--compare");
+                let runs = args.get_integer_result("repeat").unwrap_or(10);
+                let warmup = args.get_integer("warmup");
+                if runs < 2 || warmup < 0 {
+                    args.quit("--compare needs --repeat of at least 2, and --warmup can't be negative");
+                }
+                compare::compare(flags, &files, passthrough, runs as usize, warmup as usize);
+                process::exit(0);
+            }
+            // plain output is the default when it isn't going to a terminal
+            let plain = args.get_bool("plain") || ! isatty::stdout_isatty();
+            process::exit(batch::run_programs(flags, files, passthrough, args.get_bool("keep-going"), args.get_bool("compile-only"), plain));
+        }
+    }
+
+    if args.get_bool("compare") {
+        args.quit("--compare needs two programs, like 'runner --compare a.rs b.rs'");
+    }
+
+    // with -I the code comes from stdin; otherwise stdin is left alone for the program
+    let code_from_stdin = if args.get_bool("code-from-stdin") {
+        if ["lines","json","csv","pipe"].iter().any(|f| args.get_bool(f)) || args.get_string_result("grep").is_ok() {
+            args.quit("the code comes from stdin, so there is no input for -n, -g, -j, --csv or --pipe");
+        }
+        let mut code = String::new();
+        io::stdin().read_to_string(&mut code).or_die("cannot read code from stdin");
+        Some(code)
     } else {
         None
     };
+    let stdin_expression = ["expression","iterator","type"].iter().any(|f| args.get_bool(f));
 
-    args.parse_env_args().or_die("bad command line"); 
+    // a named snippet from the library takes the place of a program file
+    let loaded = if let Some(code) = code_from_stdin.as_ref().filter(|_| ! stdin_expression) {
+        // like the clipboard, a program from stdin is treated like a file
+        let path = cache::runner_directory().join("stdin.rs");
+        fs::write(&path, code).or_die("cannot write code from stdin");
+        Some(path)
+    } else if args.get_bool("paste") {
+        // the clipboard is treated like a file
+        let path = cache::runner_directory().join("paste.rs");
+        fs::write(&path, platform::paste()).or_die("cannot write pasted code");
+        Some(path)
+    } else {
+        args.get_string_result("load").ok().map(|name| snippets::find_snippet(&name))
+    };
+    // 'runner -r name' runs a program in the bin directory, from wherever it's asked for,
+    // as it was built from its source
+    if let Ok(name) = args.get_string_result("program") {
+        if args.get_bool("run") && loaded.is_none() && ! Path::new(&name).is_file() {
+            let program = programs::find(&name);
+            let source = match (cache::recorded_source(&program),cache::recorded_mode(&program)) {
+                (Some(source),_) if source.is_file() => source,
+                (Some(source),_) => args.quit(&format!("{} was built from {}, which has gone",name,source.display())),
+                (None,Some(_)) => args.quit(&format!("{} was built from an expression, so 'runner -r' can't find it",name)),
+                (None,None) => args.quit(&format!("runner doesn't know where {} came from: use its source file",name)),
+            };
+            let mode = cache::recorded_mode(&program).unwrap_or_default();
+            if let Some(i) = command_line.iter().position(|a| *a == name) {
+                command_line[i] = source.to_str().or_die("source path not valid Unicode").to_string();
+            }
+            if mode.starts_with("static") {
+                command_line.push("--static".into());
+            }
+            if mode.ends_with("optimized") {
+                command_line.push("--optimize".into());
+            }
+            args = parse_args(&layers, &command_line);
+        }
+    }
+    let program_file = if let Some(ref path) = loaded {
+        Ok(path.to_str().or_die("snippet path not valid Unicode").to_string())
+    } else {
+        args.get_string_result("program")
+    };
 
-    let program_contents = if let Ok(program) = args.get_string_result("program") {
+    let mut includes = String::new();
+    let mut deps = Vec::new();
+    let mut entry = None;
+    let program_contents = if let Ok(program) = program_file {
         let prog = Path::new(&program);
-        if program.ends_with(".rs") {
+        // scripts run with '#!' often don't have an extension
+        if program.ends_with(".rs") || (args.get_bool("script") && prog.is_file()) {
             if ! prog.is_file() {
                 args.quit("file does not exist");
             }
-            args.clear_used();
-            let (contents,has_arg_comment) = read_file_with_arg_comment(&mut args, prog);
-            if has_arg_comment {
-                args.parse_env_args().or_die("bad command line");
+            let (contents,layer,header) = read_file_with_header(prog);
+            if let Some(layer) = layer {
+                // the file's flags come before the command line
+                layers.push(layer);
+                args = parse_args(&layers, &command_line);
             }
+            includes = header.included();
+            deps = header.deps;
+            entry = header.entry;
+            features.extend(header.features);
+            toolchain = header.toolchain.or(toolchain);
             Some(contents)
         } else {
             None
@@ -123,10 +969,23 @@ fn main() {
     } else {
         None
     };
+    if let Some(toolchain) = toolchain {
+        if env::var("RUNNER_TOOLCHAIN").ok().as_ref() != Some(&toolchain) {
+            run_with_toolchain(&toolchain);
+        }
+    }
+    if ! features.is_empty() && ! args.get_bool("run") {
+        check_features(&features);
+    }
 
     let mut prelude = cache::get_prelude();
     if let Some(env_prelude) = env_prelude {
-        prelude.insert_str(0, &env_prelude);
+        // after the runner prelude, which begins with crate attributes
+        prelude.push_str(&env_prelude);
+    }
+    // cargo also looks at RUSTC_WRAPPER, so the static cache builds use it as well
+    if let Ok(wrapper) = args.get_string_result("compiler-wrapper") {
+        env::set_var("RUSTC_WRAPPER", wrapper);
     }
     let b = |p| args.get_bool(p);
 
@@ -140,7 +999,50 @@ fn main() {
         println!("runner {}",VERSION);
         return;
     }
+    if b("info") {
+        let format = args.get_string("format");
+        if format != "text" && format != "json" {
+            args.quit(&format!("--format must be text or json, not '{}'",format));
+        }
+        info::info(VERSION, format == "json");
+        return;
+    }
     let verbose = b("verbose");
+    set_verbosity(&args, extra_verbosity);
+    logging::set_dry_run(b("dry-run"));
+    timings::enable(b("timings"), b("timings-json"));
+    cache::set_raw(b("raw"));
+    let mut cargo_args = Vec::new();
+    if let Ok(jobs) = args.get_integer_result("jobs") {
+        if jobs < 1 {
+            args.quit("--jobs must be at least 1");
+        }
+        cargo_args.extend(["--jobs".to_string(), jobs.to_string()]);
+    }
+    if cache_build && args.get_string_result("program").is_err() {
+        cargo_args.append(&mut passthrough);
+    }
+    cache::set_cargo_args(cargo_args);
+
+    if b("cache-stats") {
+        gc::cache_stats();
+        return;
+    }
+    let older_than = args.get_string_result("older-than").ok().map(|s| gc::parse_age(&s)
+        .unwrap_or_else(|| args.quit(&format!("--older-than should be like 30d or 12h, not '{}'",s))));
+    if b("gc") {
+        let max_size = args.get_string_result("max-size").ok().map(|s| gc::parse_size(&s)
+            .unwrap_or_else(|| args.quit(&format!("--max-size should be like 2G or 500M, not '{}'",s))));
+        gc::gc(max_size, older_than);
+        return;
+    } else if b("clean-bin") {
+        gc::clean_bin(older_than);
+        return;
+    } else if args.get_string_result("max-size").is_ok() {
+        args.quit("--max-size goes with --gc");
+    } else if older_than.is_some() {
+        args.quit("--older-than goes with --gc or --clean-bin");
+    }
 
     if b("run") && b("compile-only") {
         args.quit("--run and compile-only make no sense together");
@@ -151,6 +1053,58 @@ fn main() {
         cache::add_aliases(aliases);
         return;
     }
+    if b("alias-list") {
+        let mut aliases: Vec<_> = cache::get_aliases().into_iter().collect();
+        aliases.sort();
+        for (name,value) in aliases {
+            println!("{} = {}",name,value);
+        }
+        return;
+    }
+    if let Ok(name) = args.get_string_result("alias-remove") {
+        if ! cache::remove_alias(&name) {
+            args.quit(&format!("no alias '{}'",name));
+        }
+        return;
+    }
+
+    if let Ok(name) = args.get_string_result("uninstall") {
+        let mut dir = install_dir();
+        if dir == Path::new("cargo") {
+            dir = crate_utils::cargo_home().join("bin");
+        }
+        let path = dir.join(name).with_extension(exe_suffix);
+        if ! path.is_file() {
+            args.quit(&format!("{} is not installed",path.display()));
+        }
+        if logging::dry_run() {
+            println!("# remove {}",path.display());
+        } else {
+            fs::remove_file(&path).or_die("cannot remove program");
+            info!("removed {}",path.display());
+        }
+        return;
+    }
+
+    if b("list") {
+        programs::list();
+        return;
+    }
+
+    if b("list-snippets") {
+        snippets::list_snippets();
+        return;
+    }
+
+    if let Ok(query) = args.get_string_result("search") {
+        snippets::search_snippets(&query);
+        return;
+    }
+
+    let tags = args.get_strings("tag");
+    if ! tags.is_empty() && args.get_string_result("save").is_err() {
+        args.quit("--tag only makes sense with --save");
+    }
 
     if b("edit-prelude") {
         let rdir = cache::runner_directory().join("prelude");
@@ -158,10 +1112,15 @@ fn main() {
         return;
     }
 
+    if b("edit-config") {
+        edit(&config::config_file());
+        return;
+    }
+
     // Static Cache Management
     let crates = args.get_strings("add");
     if crates.len() > 0 {
-        cache::create_static_cache(&crates);
+        cache::create_static_cache(&crates, args.get_string_result("registry").ok().as_deref());
         if program_contents.is_none() {
             return;
         }
@@ -169,15 +1128,71 @@ fn main() {
 
     // operations on the static cache
     let (edit_toml, build, doc, update, cleanup, crates) =
-        (b("edit"), b("build"), b("doc"), b("update"), b("cleanup"), b("crates"));
+        (b("edit") && ! b("crate-path"), b("build"), b("doc"), b("update"), b("cleanup"), b("crates"));
 
-    if edit_toml || build || doc || update || cleanup || crates {
+    if doc {
+        // which need not be in the static cache
+        docs::open_docs(args.get_string_result("program").ok());
+        return;
+    }
+    if let Ok(dir) = args.get_path_result("vendor") {
+        vendor::vendor(&dir);
+        return;
+    }
+    if let Ok(tarball) = args.get_path_result("export-cache") {
+        vendor::export_cache(&tarball);
+        return;
+    }
+    if let Ok(tarball) = args.get_path_result("import-cache") {
+        vendor::import_cache(&tarball);
+        return;
+    }
+    if let Ok(name) = args.get_string_result("remove") {
+        cache::remove_crate(&name);
+        return;
+    }
+    if let Ok(name) = args.get_string_result("why") {
+        cargo_lock::why(&cache::static_cache_dir_check(), &name);
+        return;
+    }
+    if b("unpin") && ! update {
+        args.quit("--unpin goes with --update");
+    }
+    if edit_toml || build || update || cleanup || crates {
         let maybe_argument = args.get_string_result("program");
         let static_cache = cache::static_cache_dir_check();
         if build || update {
             env::set_current_dir(&static_cache).or_die("static cache wasn't a directory?");
             if build {
                 cache::build_static_cache();
+                return;
+            }
+            // pinned crates stay as they are, unless released
+            if b("unpin") {
+                cache::unpin(maybe_argument.as_deref().ok());
+            } else {
+                cache::keep_pins(maybe_argument.as_deref().ok());
+            }
+            if logging::dry_run() {
+                // show what would change, rather than the cargo command
+                let changes = cache::update_dry_run(maybe_argument.as_deref().ok());
+                if changes.is_empty() {
+                    println!("the static cache is up to date");
+                    return;
+                }
+                let width = changes.iter().map(|c| c.package.len()).max().unwrap_or(0);
+                for c in &changes {
+                    let change = match (&c.from,&c.to) {
+                        (Some(from),Some(to)) => format!("{} -> {}",from,to),
+                        (None,Some(to)) => format!("{} (new)",to),
+                        (Some(from),None) => format!("{} (removed)",from),
+                        (None,None) => continue
+                    };
+                    println!("{:<width$} {}",c.package,change,width=width);
+                }
+                println!("{} package{} would change: 'runner --update' takes them, then 'runner --build' rebuilds the cache",
+                    changes.len(),if changes.len() == 1 {""} else {"s"});
+                return;
             } else {
                 if let Ok(package) = maybe_argument {
                     cache::cargo(&["update","--package",&package]);
@@ -187,17 +1202,6 @@ fn main() {
                 return;
             }
         } else
-        if doc {
-            let the_crate = crate_utils::proper_crate_name(
-                &if let Ok(file) =  maybe_argument {
-                    file
-                } else {
-                    "static_cache".to_string()
-                }
-            );
-            let docs = static_cache.join(&format!("target/doc/{}/index.html",the_crate));
-            open(&docs);
-        } else
         if cleanup {
             cache::cargo(&["clean"]);
         } else
@@ -216,10 +1220,31 @@ fn main() {
         return;
     }
 
-    let first_arg = args.get_string("program");
+    let first_arg = if let Some(ref path) = loaded {
+        path.to_str().unwrap().to_string()
+    } else if let Some(code) = &code_from_stdin {
+        code.trim().to_string()
+    } else {
+        args.get_string("program")
+    };
     let file = PathBuf::from(&first_arg);
-    let optimized = args.get_bool("optimize");
-    let edition = args.get_string("edition");
+    let install_as = args.get_string_result("install-as").ok();
+    let install = b("install") || install_as.is_some();
+    let optimized = (args.get_bool("optimize") && ! args.get_bool("no-optimize")) || args.get_bool("small") || install;
+    let mut edition = args.get_string("edition");
+    // a file in a Cargo project gets the project's edition, unless it's been asked for
+    let edition_given = layers.iter().skip(1).any(|(_,flags)| gives_edition(flags)) || gives_edition(&command_line);
+    if program_contents.is_some() && ! edition_given {
+        if let Some(project_edition) = crate_utils::project_edition(&file) {
+            if project_edition != edition {
+                verbose!("using edition {} from the Cargo project",project_edition);
+            }
+            edition = project_edition;
+        }
+    }
+    if ! EDITIONS.contains(&edition.as_str()) {
+        args.quit(&format!("unknown edition '{}': use one of {}",edition,EDITIONS.join(", ")));
+    }
 
     // Dynamically linking crates (experimental!)
     let (print_path, compile) = (b("crate-path"),b("compile"));
@@ -234,18 +1259,24 @@ fn main() {
                 if e.path == Path::new("") {
                     args.quit("please run 'runner --build' to update metadata");
                 }
-                // will be <cargo dir>/src/FILE.rs
-                let path = e.path.parent().unwrap().parent().unwrap();
-                if print_path {
+                // usually <cargo dir>/src/FILE.rs, but some crates have lib.rs at the top
+                let (path,_) = crate_utils::cargo_dir(e.path.parent().unwrap()).unwrap_or_else(|msg| args.quit(&msg));
+                let path = path.as_path();
+                if print_path && b("edit") {
+                    edit(path);
+                } else if print_path {
                     println!("{}",path.display());
                 } else {
-                    let ci = crate_utils::crate_info(&path.join("Cargo.toml"));
-                    // respect the crate's edition!
-                    state.edition = ci.edition;
+                    // respect the crate's edition, as cargo built it!
+                    state.edition = if e.edition.is_empty() {
+                        crate_utils::crate_info(&path.join("Cargo.toml")).edition
+                    } else {
+                        e.edition.clone()
+                    };
                     // TBD can override --features with features actually
                     // used to build this crate
                     let build_features = &e.features;
-                    println!("building crate '{}' {} at {}",e.crate_name, build_features, e.path.display());
+                    info!("building crate '{}' {} at {}",e.crate_name, build_features, e.path.display());
                     compile_crate(&args, &state, &e.crate_name, &e.path, None,
                         Vec::new(),
                         build_features.split_whitespace().map(|s| s.to_string()).collect()
@@ -253,6 +1284,30 @@ fn main() {
                 }
                 return;
             }
+            // crates which other projects have downloaded, if asked for
+            let dir = if b("search-cargo-cache") {
+                crate_utils::find_in_cargo_cache(&first_arg)
+                    .unwrap_or_else(|| args.quit("not in the static cache or Cargo's downloaded crates"))
+            } else {
+                args.quit("not found in the static cache (--search-cargo-cache also looks at the crates Cargo has downloaded)");
+            };
+            let cargo_toml = dir.join("Cargo.toml");
+            if print_path && b("edit") {
+                edit(&dir);
+            } else if print_path {
+                println!("{}",dir.display());
+            } else {
+                let ci = crate_utils::crate_info(&cargo_toml);
+                state.edition = ci.edition;
+                let source = dir.join("src").join("lib.rs");
+                if ! source.is_file() {
+                    args.quit(&format!("{} has no src/lib.rs",dir.display()));
+                }
+                let features = crate_utils::default_features(&cargo_toml);
+                info!("building crate '{}' {} at {}",ci.name, features.join(" "), dir.display());
+                compile_crate(&args, &state, &crate_utils::proper_crate_name(&ci.name), &source, None, Vec::new(), features);
+            }
+            return;
         } else
         if compile { // either a cargo directory or a Rust source file
             if ! file.exists() {
@@ -278,7 +1333,7 @@ fn main() {
                 let name = crate_utils::path_file_name(&file.with_extension(""));
                 (name, file.clone())
             };
-            println!("building crate '{}' at {}",crate_name, crate_path.display());
+            info!("building crate '{}' at {}",crate_name, crate_path.display());
             compile_crate(&args, &state, &crate_name, &crate_path, None,  Vec::new(),Vec::new());
             return;
         } else { // we no longer go for wild goose chase to find crates in the Cargo cache
@@ -286,56 +1341,165 @@ fn main() {
         }
     }
 
-    let static_state = b("static") && ! b("dynamic");
-    let state = State::exe(static_state,optimized, &edition);
+    // installed programs must not depend on the dynamic cache
+    let backend = exec_backend::ExecBackend::new(&args);
+    // nor can programs run in a container
+    let static_state = install || ! backend.is_local() || ((b("static") || b("small")) && ! b("dynamic"));
+    let mut state = State::exe(static_state,optimized, &edition).with_profile(&args);
+    if ! backend.is_local() {
+        // priority and CPUs are for programs run here
+        if args.get_integer_result("nice").is_ok() || args.get_string_result("cpus").is_ok() {
+            args.quit("--nice and --cpus don't go with --docker");
+        }
+        state.crt_static = true;
+    }
 
     // we'll pass rest of arguments to program
-    let program_args = args.get_strings("args");
+    let mut program_args = args.get_strings("args");
+    if loaded.is_some() || code_from_stdin.is_some() {
+        // with --load or -I, there is no program argument to consume
+        if let Ok(arg) = args.get_string_result("program") {
+            program_args.insert(0, arg);
+        }
+    }
+    program_args.extend(passthrough);
 
+    timings::phase("args");
     let mut expression = true;
     use cache::quote;
-    let mut code = if b("expression") {
-        // Evaluating an expression: just debug print it out.
-        format!("println!(\"{{:?}}\",{});", quote(first_arg))
+    // values are debug printed, unless asked otherwise
+    let fmt = if b("display") {"display".to_string()} else {args.get_string("output-format")};
+    if ! ["debug","display","json"].contains(&fmt.as_str()) {
+        args.quit(&format!("--output-format must be debug, display or json, not '{}'",fmt));
+    }
+    let fmt = fmt.as_str();
+    if b("bytes") && ! b("lines") {
+        args.quit("--bytes goes with -n");
+    }
+    if args.get_string_result("acc").is_ok() && (! b("lines") || b("bytes")) {
+        args.quit("--acc goes with -n (but not --bytes)");
+    }
+    let grep = args.get_string_result("grep").ok();
+    if b("pipe") && (grep.is_some() || ["expression","iterator","type","lines","json","csv"].iter().any(|f| b(f))) {
+        args.quit("--pipe is its own kind of expression, and doesn't go with -e, -i, --type, -n, -g, -j or --csv");
+    }
+    if grep.is_some() && ["expression","iterator","type","lines","json","csv"].iter().any(|f| b(f)) {
+        args.quit("-g goes over the lines itself, and doesn't go with -e, -i, --type, -n, -j or --csv");
+    }
+    // with several -e, each expression gets the value of the one before as 'it'
+    // (with --pipe, as 'input')
+    let value = if b("pipe") {"input"} else {"it"};
+    let mut pipeline = String::new();
+    let first_arg = if let Some(last) = stages.last() {
+        pipeline += &format!("let {} = {};\n",value,quote(first_arg));
+        for stage in &stages[..stages.len()-1] {
+            pipeline += &format!("let {} = {};\n",value,quote(stage.clone()));
+        }
+        last.clone()
+    } else {
+        first_arg
+    };
+    let mut code = if b("type") {
+        // the expression is not run, since only its type is wanted
+        format!("{}let _: () = {};", pipeline, quote(first_arg))
+    } else if b("expression") {
+        // Evaluating an expression: just print it out.
+        format!("{}println!({});", pipeline, format_args(fmt,&quote(first_arg)))
     } else
     if b("iterator") {
         // The expression is anything that implements IntoIterator
-        format!("for val in {} {{\n println!(\"{{:?}}\",val);\n}}", quote(first_arg))
-    } else
-    if b("lines") {
-        // The variable 'line' is available to an expression, evaluated for each line in stdin
-        // But if the expression ends with '}' then don't dump out this value!
-        let first_arg = quote(first_arg);
-        let stmt = first_arg.trim_end().ends_with('}');
-        let mut s = String::from("
-            let stdin = io::stdin();
-            for line in stdin.lock().lines() {
-                let line = line?;
-        ");
-        s += &if ! stmt {
-            format!("let val = {};\nprintln!(\"{{:?}}\",val);", first_arg)
+        let mut iter = format!("({}).into_iter()", quote(first_arg));
+        if let Ok(n) = args.get_integer_result("take") {
+            iter += &format!(".take({})",n);
+        }
+        let mut body = String::new();
+        let sep = args.get_string_result("sep").ok();
+        if let Some(sep) = &sep {
+            body += &format!(" if i > 0 {{ print!(\"{{}}\",{:?}); }}\n",sep);
+        }
+        if b("enumerate") {
+            body += " print!(\"{}: \",i);\n";
+        }
+        let print = if sep.is_some() {"print"} else {"println"};
+        body += &format!(" {}!({});\n",print,format_args(fmt,"val"));
+        // the index is only needed for separators and --enumerate
+        let mut s = if sep.is_none() && ! b("enumerate") {
+            format!("for val in {} {{\n{}}}",iter,body)
         } else {
-            format!("  {};",first_arg)
+            format!("for (i,val) in {}.enumerate() {{\n{}}}",iter,body)
         };
-        s += "\n}";
+        if sep.is_some() {
+            s += "\nprintln!();";
+        }
         s
+    } else
+    if b("pipe") {
+        pipe_code(pipeline, quote(first_arg), fmt)
+    } else if let Some(pattern) = grep {
+        grep_code(&args, &pattern, quote(first_arg), fmt)
+    } else if b("csv") {
+        csv_code(&args, quote(first_arg), fmt)
+    } else if b("lines") && b("bytes") {
+        bytes_code(&args, quote(first_arg), fmt)
+    } else if b("lines") {
+        lines_code(&args, quote(first_arg), fmt)
+    } else if b("json") {
+        json_code(&args, quote(first_arg), fmt)
     } else { // otherwise, just a file
         expression = false;
         program_contents.or_die("no .rs file")
     };
+    if install && expression && install_as.is_none() {
+        args.quit("an expression has no name to install as: use --install-as");
+    }
+    if fmt == "json" && expression && ! code.contains("extern crate serde_json;") {
+        code = format!("extern crate serde_json;\n{}",code);
+    }
+
+    if let Ok(name) = args.get_string_result("save") {
+        // expressions need their flags to be useful later
+        let arg_comment = if expression {
+            snippet_arg_comment(&args)
+        } else {
+            String::new()
+        };
+        let path = snippets::save_snippet(&name, &code, &arg_comment);
+        if ! tags.is_empty() {
+            snippets::set_tags(&name, tags);
+        }
+        verbose!("saved snippet {:?} to {}",name,path.display());
+    }
 
     // ALL executables go into the Runner bin directory...
     let mut bin = cache::runner_directory().join("bin");
     let mut externs = Vec::new();
+    let mut source_map = None;
 
+    // a snippet may have its own entry point, called by the generated program
+    let entry_call = if expression {None} else {compile::entry_call(&mut code, entry)};
     // proper Rust programs are accepted (this is a bit rough)
     let proper = code.find("fn main").is_some();
+    if proper && entry_call.is_some() {
+        args.quit("a program with an entry point can't have fn main as well");
+    }
     let (rust_file, program) = if ! proper {
         // otherwise we must create a proper program from the snippet
         // and write this as a file in the Runner bin directory...
         let mut extern_crates = args.get_strings("extern");
-        let wild_crates = args.get_strings("wild");
-        let macro_crates = args.get_strings("macro");
+        let mut wild_crates = args.get_strings("wild");
+        let mut macro_crates = args.get_strings("macro");
+        // crates which config.toml says are always imported like -X or -M
+        extern_crates.retain(|c| {
+            let list = match config::CONFIG.imports.get(c).map(|s| s.as_str()) {
+                Some("wild") => &mut wild_crates,
+                Some("macro") => &mut macro_crates,
+                _ => return true
+            };
+            if ! list.contains(c) {
+                list.push(c.clone());
+            }
+            false
+        });
         if wild_crates.len() > 0 {
             extern_crates.extend(wild_crates.iter().cloned());
         }
@@ -348,23 +1512,36 @@ fn main() {
         if ! extra.is_empty() {
             extra.push(';');
         }
+        extra += &entry_call.unwrap_or_default();
         let maybe_prelude = if b("no-prelude") {
-            "".into()
+            includes.clone()
         } else {
-            prelude
+            prelude + &includes
         };
 
-        let (massaged_code, deduced_externs)
-            = massage_snippet(code, maybe_prelude, extern_crates, wild_crates, macro_crates, extra, edition=="2018");
+        let original = code.clone();
+        // the runner prelude and env.rs may well predate 2024
+        let maybe_prelude = if edition.as_str() >= "2024" {
+            code = compile::for_edition_2024(&code);
+            compile::for_edition_2024(&maybe_prelude)
+        } else {
+            maybe_prelude
+        };
+        if ! expression {
+            code = compile::local_modules(&code, file.parent().unwrap_or(Path::new(".")));
+        }
+        let (massaged_code, deduced_externs, line_map)
+            = massage_snippet(code, maybe_prelude, extern_crates, wild_crates, macro_crates, extra, edition != "2015");
         code = massaged_code;
         externs = deduced_externs;
         if ! expression {
             bin.push(file.file_name().unwrap());
             bin.set_extension("rs");
-        } else { // we make up a name...
-            bin.push("tmp.rs");
+            // so that errors can refer to the user's file
+            source_map = Some(diagnostics::SourceMap::new(&bin, &file, line_map, original));
+        } else { // we make up a name from the code and how it's built...
+            bin.push(format!("tmp-{}.rs",strutil::content_hash(&[&code,&state.describe()])));
         }
-        fs::write(&bin,&code).or_die("cannot write code");
         let program = bin.with_extension(exe_suffix);
         (bin, program)
     } else {
@@ -376,64 +1553,371 @@ fn main() {
         // the 'proper' case - use the file name part
         bin.push(file.file_name().unwrap());
         let program = bin.with_extension(exe_suffix);
-        (file, program)
+        if includes.is_empty() {
+            (file.clone(), program)
+        } else {
+            // a copy with the included files goes into the bin directory
+            code += "\n";
+            code += &includes;
+            (bin, program)
+        }
     };
 
+    // what rustc will get, including prelude and wrapper
+    if b("show-code-only") {
+        print!("{}",code);
+        return;
+    }
+    if b("show-code") {
+        eprint!("{}",code);
+    }
+
+    if let Ok(dir) = args.get_string_result("emit-project") {
+        let name = if expression {
+            "snippet".to_string()
+        } else {
+            crate_utils::path_file_name(&rust_file.with_extension(""))
+        };
+        project::emit_project(Path::new(&dir), &name, &code, &externs, &state.edition);
+        return;
+    }
+
+    timings::phase("codegen");
+    let dry_run = logging::dry_run();
+    // held until the program is built
+    let lock = if ! dry_run {
+        Some(cache::lock_program(&program))
+    } else {
+        None
+    };
+    if ! proper || ! includes.is_empty() {
+        if dry_run {
+            println!("# write generated program to {}",rust_file.display());
+        } else {
+            fs::write(&rust_file,&code).or_die("cannot write code");
+        }
+    }
+
+    // scripts are only rebuilt when they (or how they're built) change
+    let stamp = cache::beside(&program, "stamp");
+    let hash = strutil::content_hash(&[&code,&state.describe()]);
+    let unchanged = b("script") && program.exists()
+        && fs::read_to_string(&stamp).is_ok_and(|s| s == hash);
+    // only worked out when there's something to build. A program file or a snippet with
+    // 'mod' files may change without its code changing, and so can a '//: dep' crate
+    let from_files = proper || code.contains("#[path=") || ! deps.is_empty();
+    let stored = if b("run") || unchanged || from_files || dry_run {
+        None
+    } else {
+        let flags = [args.get_strings("cfg"),args.get_strings("features"),args.get_strings("extern"),
+            args.get_string_result("link").ok().into_iter().collect()].concat().join(" ");
+        let extra = format!("libc={} RUSTFLAGS={}",b("libc"),env::var("RUSTFLAGS").unwrap_or_default());
+        Some(cache::store_path(&[&code,&state.describe(),&flags,&extra], &state))
+    };
     if b("run") {
         if ! program.exists() {
             args.quit(&format!("program {:?} does not exist",program));
         }
+    } else if unchanged {
+        verbose!("{} is up to date",program.display());
+    } else if let Some(stored) = stored.as_ref().filter(|s| s.is_file() && ! b("fresh") && ! b("type")) {
+        cache::fetch_from_store(stored, &program);
+        let source = if expression {None} else {Some(file.as_path())};
+        cache::record_build(&program, source, &state.mode());
+        if b("script") {
+            fs::write(&stamp,&hash).or_die("cannot write build stamp");
+        }
     } else {
-        if ! compile_crate(&args,&state,"",&rust_file,Some(&program), externs, Vec::new()) {
+        if stored.is_some() {
+            // the program may be linked to the store, which the linker must not write through
+            let _ = fs::remove_file(&program);
+        }
+        if state.small && ! b("lto") && ! (externs.is_empty() && args.get_strings("extern").is_empty()) {
+            verbose!("no LTO, since the static cache is built without LLVM bitcode");
+            state.lto = false;
+        }
+        // crates from '//: dep' lines are not in the cache, so they get an explicit path
+        let local_deps: Vec<(String,PathBuf)> = deps.iter()
+            .map(|(name,dir)| compile::local_dependency(&args, &state, name.as_deref(), dir))
+            .collect();
+        externs.retain(|e| ! local_deps.iter().any(|(name,_)| name == e));
+        if state.build_static {
+            let mut wanted = externs.clone();
+            wanted.extend(args.get_strings("extern"));
+            cache::add_missing_crates(&wanted, b("auto-add"), args.get_string_result("registry").ok().as_deref());
+        }
+        let make_builder = |externs: Vec<String>| {
+            let mut builder = rustc_command(&args,&state,"",&rust_file,Some(&program), externs, Vec::new());
+            for (name,lib) in &local_deps {
+                builder.arg("--extern").arg(format!("{}={}",name,lib.display()));
+            }
+            // expressions are always new, sccache will not cache incremental builds
+            // and rustc can't do LTO incrementally. But the daemon's expressions share
+            // their state (as one crate), so the work on the prelude is kept warm
+            let shared = expression && daemon::serving();
+            if (! expression || shared) && ! b("fresh") && ! state.lto && env::var("RUSTC_WRAPPER").unwrap_or_default().is_empty() {
+                let key = if shared {
+                    format!("expressions {}",state.describe())
+                } else {
+                    format!("{} {}",rust_file.display(),state.describe())
+                };
+                if shared {
+                    builder.arg("--crate-name").arg("expression");
+                }
+                builder.arg("-C").arg(format!("incremental={}",cache::incremental_dir(&key).display()));
+            }
+            builder
+        };
+        let builder = make_builder(externs.clone());
+        if b("type") {
+            let found = compile::print_type(&args, builder);
+            process::exit(if found {0} else {1});
+        }
+        let mut compiled = run_rustc(&args, builder, source_map.as_ref());
+        // rustc may want crates which weren't deduced, like 'regex::Regex::new(...)' without a 'use'
+        if ! compiled && state.build_static {
+            let missing = diagnostics::missing_crates(&compile::last_errors());
+            let added = cache::add_crates_rustc_wanted(&missing, b("auto-add"), args.get_string_result("registry").ok().as_deref());
+            if ! added.is_empty() {
+                let mut externs = externs.clone();
+                externs.extend(added);
+                compiled = run_rustc(&args, make_builder(externs), source_map.as_ref());
+            }
+        }
+        timings::phase("compile");
+        if ! compiled {
+            if fmt == "json" && expression {
+                info!("(--output-format json needs a value which implements serde::Serialize)");
+            }
+            timings::report();
             process::exit(1);
         }
-        if verbose {
-            println!("compiled {:?} successfully",rust_file);
+        verbose!("compiled {:?} successfully",rust_file);
+        platform::codesign(&program);
+        if let Some(stored) = &stored {
+            cache::put_in_store(&program, stored);
         }
+        if ! dry_run {
+            let source = if expression {None} else {Some(file.as_path())};
+            cache::record_build(&program, source, &state.mode());
+        }
+        if state.small && ! dry_run {
+            let size = fs::metadata(&program).or_die("cannot find program").len();
+            info!("{} is {:.1} KiB",program.display(),size as f64 / 1024.0);
+        }
+        if b("script") && ! dry_run {
+            fs::write(&stamp,&hash).or_die("cannot write build stamp");
+        }
+    }
+    drop(lock);
+    if b("no-run") {
+        timings::report();
+        return;
     }
 
-    if b("compile-only") {
-        let file_name = rust_file.file_name().or_die("no file name?");
-        let out_dir = args.get_path("output");
+    if b("compile-only") || install {
+        let file_name = if let Some(ref name) = install_as {
+            Path::new(name).as_os_str()
+        } else {
+            rust_file.file_name().or_die("no file name?")
+        };
+        let out_dir = if install {
+            install_dir()
+        } else {
+            args.get_path("output")
+        };
         let home = if out_dir == Path::new("cargo") {
             let home = crate_utils::cargo_home().join("bin");
-            if ! home.is_dir() {
+            if ! home.is_dir() && ! dry_run {
                 // With Windows, standalone installer does not create this directory
                 // (may well be a Bugge)
                 fs::create_dir(&home).or_die("could not create Cargo bin directory");
-                println!("creating Cargo bin directory {}\nEnsure it is on your PATH",home.display());
+                info!("creating Cargo bin directory {}\nEnsure it is on your PATH",home.display());
             }
             home
         } else {
             out_dir
         };
-        let here = home.join(file_name).with_extension(exe_suffix);
-        println!("Copying {} to {}",program.display(),here.display());
-        fs::copy(&program,&here).or_die("cannot copy program");
+        // -o is a directory if it is one already or ends with a slash, otherwise the
+        // program itself. Either way, missing directories are created
+        let is_dir = install || home.is_dir() || home.to_string_lossy().ends_with(&['/','\\'][..]);
+        let here = if is_dir {
+            home.join(file_name).with_extension(exe_suffix)
+        } else {
+            home
+        };
+        let dir = here.parent().unwrap_or(Path::new("."));
+        if ! dir.as_os_str().is_empty() && ! dir.is_dir() {
+            if dry_run {
+                println!("# create {}",dir.display());
+            } else {
+                fs::create_dir_all(dir).or_die("could not create output directory");
+            }
+        }
+        let source = here.with_extension("rs");
+        let same_file = |a: &Path, b: &Path| fs::canonicalize(a).ok().is_some_and(|a| fs::canonicalize(b).ok() == Some(a));
+        if b("emit-source") && same_file(&source, &file) {
+            args.quit(&format!("--emit-source would overwrite {}",file.display()));
+        }
+        if dry_run {
+            println!("# copy {} to {}",program.display(),here.display());
+            if b("emit-source") {
+                println!("# write generated program to {}",source.display());
+            }
+        } else {
+            info!("Copying {} to {}",program.display(),here.display());
+            fs::copy(&program,&here).or_die("cannot copy program");
+            if b("emit-source") {
+                fs::write(&source,&code).or_die("cannot write program source");
+            }
+        }
+        timings::report();
         return;
     }
 
     // Finally run the compiled program
     let ch = cache::get_cache(&state);
-    let mut builder = process::Command::new(&program);
-    if ! state.build_static {
+    let chdir = args.get_string_result("chdir").ok();
+    if let Some(dir) = &chdir {
+        if ! Path::new(dir).is_dir() {
+            args.quit(&format!("--chdir: no such directory '{}'",dir));
+        }
+    }
+    let mut envs = Vec::new();
+    if ! state.build_static && ! state.rpath {
         // must make the dynamic cache visible to the program!
         if cfg!(windows) {
-            // Windows resolves DLL references on the PATH
+            // Windows resolves DLL references on the PATH, including std's
             let path = env::var("PATH").unwrap();
-            let new_path = format!("{};{}",path,ch.display());
-            builder.env("PATH",new_path);
+            let new_path = format!("{};{};{}",path,*RUSTUP_LIB,ch.display());
+            envs.push(("PATH".to_string(),new_path));
+        } else if cfg!(target_os = "macos") {
+            // the fallback path doesn't hide the system's libraries
+            envs.push(("DYLD_FALLBACK_LIBRARY_PATH".to_string(),format!("{}:{}",*RUSTUP_LIB,ch.display())));
         } else {
             // whereas POSIX requires LD_LIBRARY_PATH
-            builder.env("LD_LIBRARY_PATH",format!("{}:{}",*RUSTUP_LIB,ch.display()));
+            envs.push(("LD_LIBRARY_PATH".to_string(),format!("{}:{}",*RUSTUP_LIB,ch.display())));
+        }
+    }
+    // --env comes after .env, so it wins
+    if b("dotenv") {
+        envs.extend(read_dotenv(Path::new(".env")));
+    }
+    for var in args.get_strings("env") {
+        let (key,value) = var.split_once('=')
+            .unwrap_or_else(|| args.quit(&format!("--env needs KEY=VALUE, not '{}'",var)));
+        envs.push((key.to_string(),value.to_string()));
+    }
+    let mut builder = backend.command(&program, chdir.as_deref().map(Path::new), &envs, &program_args);
+    if let Ok(nice) = args.get_integer_result("nice") {
+        platform::set_priority(&mut builder, nice).unwrap_or_else(|e| args.quit(&e));
+    }
+    if let Ok(cpus) = args.get_string_result("cpus") {
+        let cpus = platform::parse_cpus(&cpus).unwrap_or_else(|e| args.quit(&e));
+        platform::set_cpus(&mut builder, &cpus).unwrap_or_else(|e| args.quit(&e));
+    }
+    let redirect = platform::Redirect {
+        out: args.get_string_result("out-file").ok(),
+        err: args.get_string_result("err-file").ok(),
+        append: b("append"),
+    };
+    if dry_run {
+        println!("{}{}",logging::shell_command(&builder),redirect.shell_redirects());
+        return;
+    }
+    // the output is checked here, so again not by the daemon's client
+    let snapshot_update = b("snapshot-update");
+    if b("capture") {
+        if b("verify") || b("snapshot") || snapshot_update || args.get_integer_result("repeat").is_ok() {
+            args.quit("--capture doesn't go with --snapshot, --verify or --repeat");
+        }
+        if redirect.out.is_some() || redirect.err.is_some() {
+            args.quit("--capture reports the output itself, so doesn't go with --out-file or --err-file");
+        }
+        let code = capture::run(&mut builder);
+        timings::phase("run");
+        timings::report();
+        process::exit(expected_exit(&args, code));
+    }
+    if b("snapshot") || snapshot_update {
+        if expression {
+            args.quit("--snapshot needs a program file, for the snapshot to go next to");
         }
+        if redirect.out.is_some() || redirect.err.is_some() {
+            args.quit("--snapshot compares the output itself, so doesn't go with --out-file or --err-file");
+        }
+        let code = snapshot::run(&mut builder, &file.with_extension("snap"), snapshot_update);
+        timings::phase("run");
+        timings::report();
+        process::exit(code);
+    }
+    if b("verify") {
+        if expression {
+            args.quit("--verify needs a program file with '//=>' comments");
+        }
+        if redirect.out.is_some() || redirect.err.is_some() {
+            args.quit("--verify checks the output itself, so doesn't go with --out-file or --err-file");
+        }
+        let code = verify::run(&mut builder, &file);
+        timings::phase("run");
+        timings::report();
+        process::exit(code);
+    }
+    let repeat = args.get_integer_result("repeat").ok();
+    let expecting = args.get_integer_result("expect-status").is_ok() || b("expect-fail");
+    // the client doesn't know about priority and CPUs, which may also come from
+    // config.toml, env.rs or the program's header
+    let own_process = args.get_integer_result("nice").is_ok() || args.get_string_result("cpus").is_ok();
+    // repeated runs are timed here, and not by the daemon's client (nor are expected failures)
+    if daemon::serving() && repeat.is_none() && ! expecting && ! own_process {
+        daemon::run_in_client(&builder, &redirect);
+        timings::report();
+        return;
     }
-    let status = builder.args(&program_args)
-        .status()
+    redirect.apply(&mut builder);
+    trace!("running {:?}",builder);
+    if let Some(runs) = repeat {
+        let warmup = args.get_integer("warmup");
+        if runs < 1 || warmup < 0 {
+            args.quit("--repeat must be at least 1, and --warmup can't be negative");
+        }
+        let code = expected_exit(&args, timings::repeat(&mut builder, runs as usize, warmup as usize));
+        timings::phase("run");
+        timings::report();
+        if code != 0 {
+            process::exit(code);
+        }
+        return;
+    }
+    let status = platform::run_program(&mut builder)
         .or_then_die(|e| format!("can't run program {:?}: {}",program,e));
+    timings::phase("run");
+    timings::report();
 
-    if ! status.success() {
-        process::exit(status.code().unwrap_or(-1));
+    let code = expected_exit(&args, platform::exit_code(&status));
+    if code != 0 {
+        process::exit(code);
+    }
+}
+
+// with --expect-status or --expect-fail, runner succeeds only if the program
+// exits as expected, so shell tests can check snippets which should fail
+fn expected_exit(args: &lapp::Args, code: i32) -> i32 {
+    let (ok,expected) = if let Ok(status) = args.get_integer_result("expect-status") {
+        if args.get_bool("expect-fail") {
+            args.quit("--expect-status and --expect-fail don't go together");
+        }
+        (code == status, format!("exit status {}",status))
+    } else if args.get_bool("expect-fail") {
+        (code != 0, "to fail".to_string())
+    } else {
+        return code;
+    };
+    if ok {
+        0
+    } else {
+        info!("expected {}, but the program exited with {}",expected,code);
+        1
     }
 }
 
@@ -18,9 +18,31 @@ fn as_str(v: &json::JsonValue) -> &str {
     v.as_str().unwrap()
 }
 
-fn read_entry(line: &str) -> Option<(String,String,Version,String,String,String)> {
+// cargo package ids used to be 'name version (source)', and are now like
+// 'registry+https://github.com/rust-lang/crates.io-index#name@version'
+// (or 'path+file:///dir/name#version' when the name matches the last path part)
+pub fn package_name_version(package_id: &str) -> (String,String) {
     use crate::strutil::next_2;
 
+    if package_id.contains(' ') {
+        let (package,vs) = next_2(package_id.split_whitespace());
+        return (package.into(), vs.into());
+    }
+    let idx = package_id.rfind('#').or_die("bad cargo package id");
+    let (source,spec) = (&package_id[0..idx], &package_id[idx+1..]);
+    if let Some(at) = spec.find('@') {
+        (spec[0..at].into(), spec[at+1..].into())
+    } else {
+        let source = source.split('?').next().unwrap().trim_end_matches('/');
+        let name = source.rsplit('/').next().unwrap();
+        (name.into(), spec.into())
+    }
+}
+
+// package, crate name, version, features, library file, source path and edition
+type Entry = (String,String,Version,String,String,String,String);
+
+fn read_entry(line: &str) -> Option<Entry> {
     if let Ok(doc) = json::parse(line) {
         let features = doc["features"].members().map(as_str).join(' ');
         let filenames = &doc["filenames"][0];
@@ -33,8 +55,7 @@ fn read_entry(line: &str) -> Option<(String,String,Version,String,String,String)
         let ext = path.extension();
         if ! (ext.is_none() || ext.unwrap() == "exe") { // ignore build artifacts
             // package_id has version
-            let package_id = as_str(&doc["package_id"]).split_whitespace();
-            let (package,vs) = next_2(package_id);
+            let (package,vs) = package_name_version(as_str(&doc["package_id"]));
 
             // but look for _crate name_ in name field
             let name = as_str(&doc["target"]["name"]);
@@ -42,10 +63,11 @@ fn read_entry(line: &str) -> Option<(String,String,Version,String,String,String)
             // get the cached source path
             let path = Path::new(as_str(&doc["target"]["src_path"]));
 
-            let vs = Version::parse(vs).or_die("bad semver");
+            let vs = Version::parse(&vs).or_die("bad semver");
             let filename = filename.to_str().or_die("filename not valid Unicode");
             let src_path = path.to_str().or_die("cached path not valid Unicode");
-            Some((package.into(),name.into(),vs,features,filename.into(),src_path.into()))
+            let edition = doc["target"]["edition"].as_str().unwrap_or("2015");
+            Some((package,name.into(),vs,features,filename.into(),src_path.into(),edition.into()))
         } else {
             None
         }
@@ -54,11 +76,11 @@ fn read_entry(line: &str) -> Option<(String,String,Version,String,String,String)
     }
  }
 
-fn file_name(cache: &Path) -> PathBuf {
+pub fn file_name(cache: &Path) -> PathBuf {
     cache.join("cargo.meta")
 }
 
-#[derive(Debug)]
+#[derive(Debug,Clone)]
 pub struct MetaEntry {
     pub package: String,
     pub crate_name: String,
@@ -67,8 +89,11 @@ pub struct MetaEntry {
     debug_name: String,
     release_name: String,
     pub path: PathBuf,
+    // the edition cargo built it with (empty for metadata from older runners)
+    pub edition: String,
 }
 
+#[derive(Clone)]
 pub struct Meta {
     entries: Vec<MetaEntry>
 }
@@ -108,12 +133,29 @@ impl Meta {
                 debug_name: parts[4].into(),
                 release_name: parts[5].into(),
                 path: PathBuf::from(opt_field(&parts,6)),
+                edition: opt_field(&parts,7),
             });
         }
         Meta {
             entries: v
         }
     }
+    // just the crate names, without parsing the whole file (for shell completion)
+    pub fn crate_names(cache: &Path) -> Vec<String> {
+        let contents = fs::read_to_string(file_name(cache)).unwrap_or_default();
+        let mut names: Vec<String> = contents.lines()
+            .filter_map(|line| line.split(',').nth(1))
+            .map(|s| s.to_string())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    pub fn entries(&self) -> &[MetaEntry] {
+        &self.entries
+    }
+
     pub fn get_meta_entries<'a>(&'a self, name: &str) -> Vec<&'a MetaEntry> {
         self.entries.iter()
             .filter(|e| e.package == name || e.crate_name == name)
@@ -143,8 +185,8 @@ impl Meta {
 
     pub fn dump_crates (&mut self, maybe_names: Vec<String>, verbose: bool) {
         if maybe_names.len() > 0 {
-            let packages = if verbose {
-                Some(cargo_lock::read_cargo_lock(&static_cache_dir()).package)
+            let lock = if verbose {
+                Some(cargo_lock::read_cargo_lock(&static_cache_dir()))
             } else {
                 None
             };
@@ -153,9 +195,12 @@ impl Meta {
                 if entries.len() > 0 {
                     for e in entries {
                         println!("{} = \"{}\"",e.package,e.version);
-                        if let Some(ref packages) = packages {
+                        if verbose && ! e.edition.is_empty() {
+                            println!("\t(edition {})",e.edition);
+                        }
+                        if let Some(ref lock) = lock {
                             let version = e.version.to_string();
-                            print_dependencies(&e.package, &version, &packages, 1);
+                            print_dependencies(&e.package, &version, lock, 1);
                         }
                     }
                 } else {
@@ -176,7 +221,7 @@ impl Meta {
         for line in txt.lines() {
             // note that features is in form '"foo","bar"' which we
             // store as 'foo bar'
-            if let Some((package,crate_name,vs,features,filename,path)) = read_entry(line) {
+            if let Some((package,crate_name,vs,features,filename,path,edition)) = read_entry(line) {
                 let crate_name = proper_crate_name(&crate_name);
                 self.entries.push(MetaEntry{
                     package: package,
@@ -186,6 +231,7 @@ impl Meta {
                     debug_name: filename,
                     release_name: String::new(),
                     path: PathBuf::from(path),
+                    edition,
                 });
             }
         }
@@ -193,12 +239,12 @@ impl Meta {
 
     pub fn release(&mut self, txt: String) {
         for line in txt.lines() {
-            if let Some((name,_,vs,_,filename,_)) = read_entry(line) {
+            if let Some((name,_,vs,_,filename,_,_)) = read_entry(line) {
                 if let Some(entry) = self.entries.iter_mut()
                     .find(|e| e.package == name && e.version == vs) {
                         entry.release_name = filename;
                 } else {
-                    eprintln!("cannot find {} in release build",name);
+                    info!("cannot find {} in release build",name);
                 }
             }
         }
@@ -208,27 +254,25 @@ impl Meta {
         let meta_f = file_name(cache);
         let mut f = File::create(&meta_f).or_die("cannot create cargo.meta");
         for e in self.entries {
-            write!(f,"{},{},{},{},{},{},{}\n",
+            write!(f,"{},{},{},{},{},{},{},{}\n",
                 e.package,e.crate_name,e.version,e.features,
                 e.debug_name,e.release_name,
-                e.path.display()
+                e.path.display(),e.edition
             ).or_die("i/o?");
         }
     }
 }
 
-fn print_dependencies(package: &str, version: &str, packages: &[cargo_lock::Package], indent: u32) {
-    let p = packages.iter()
+fn print_dependencies(package: &str, version: &str, lock: &cargo_lock::CargoLock, indent: u32) {
+    let p = lock.package.iter()
         .find(|p| p.name == package && p.version == version)
         .or_die("cannot find package in static cache Cargo.lock");
     let indents = (0..indent).map(|_| '\t').collect::<String>();
     if let Some(ref deps) = p.dependencies {
         for d in deps.iter() {
-            let mut iter = d.split_whitespace();
-            let pname = iter.next().unwrap();
-            let version = iter.next().unwrap();
-            println!("{}{} = \"{}\"", indents, pname, version);
-            print_dependencies(pname, version, packages, indent + 1);
+            let d = lock.find(d).or_die("cannot find dependency in static cache Cargo.lock");
+            println!("{}{} = \"{}\"", indents, d.name, d.version);
+            print_dependencies(&d.name, &d.version, lock, indent + 1);
         }
     }
 }
@@ -3,17 +3,36 @@
 // and some Windows/MacOS limitations.
 use std::path::Path;
 use std::env;
-use std::process::Command;
+use std::fs;
+use std::io;
+use std::process::{Command,ExitStatus};
+#[cfg(unix)]
+use std::sync::atomic::{AtomicI32,Ordering};
 use super::es::traits::*;
 extern crate open;
 
-pub fn open(p: &Path) {
+// a file, or a URL
+pub fn open<P: AsRef<std::ffi::OsStr>>(p: P) {
     open::that(p).or_die("cannot open");
 }
 
-pub fn edit(p: &Path) {
+// Apple Silicon kills programs which load unsigned code, and a dylib rewritten
+// in place can lose its signature, so we sign ad-hoc as the linker would
+pub fn codesign(path: &Path) {
+    if ! cfg!(all(target_os = "macos", target_arch = "aarch64")) || crate::logging::dry_run() {
+        return;
+    }
+    trace!("codesign -s - -f {}",path.display());
+    match Command::new("codesign").args(["-s","-","-f"]).arg(path).output() {
+        Ok(o) if o.status.success() => (),
+        Ok(o) => info!("cannot sign {}: {}",path.display(),String::from_utf8_lossy(&o.stderr).trim()),
+        Err(e) => info!("cannot run codesign: {}",e),
+    }
+}
+
+fn default_editor() -> String {
     // Respect POSIX
-    let editor = if let Ok(ed) = env::var("VISUAL") {
+    if let Ok(ed) = env::var("VISUAL") {
         ed
     } else
     if let Ok(ed) = env::var("EDITOR") {
@@ -29,7 +48,250 @@ pub fn edit(p: &Path) {
         "write".into()
     } else {
         "open".into()
+    }
+}
+
+// the program's output and errors may go to files rather than ours
+#[derive(Default)]
+pub struct Redirect {
+    pub out: Option<String>,
+    pub err: Option<String>,
+    pub append: bool,
+}
+
+impl Redirect {
+    fn open(&self, file: &str) -> fs::File {
+        let mut options = fs::OpenOptions::new();
+        if self.append {
+            options.append(true);
+        } else {
+            options.write(true).truncate(true);
+        }
+        options.create(true).open(file).or_then_die(|e| format!("cannot write to {}: {}",file,e))
+    }
+
+    pub fn apply(&self, builder: &mut Command) {
+        if let Some(out) = &self.out {
+            builder.stdout(self.open(out));
+        }
+        if let Some(err) = &self.err {
+            builder.stderr(self.open(err));
+        }
+    }
+
+    // like a shell would say it, for --dry-run
+    pub fn shell_redirects(&self) -> String {
+        let op = if self.append {">>"} else {">"};
+        let mut res = String::new();
+        if let Some(out) = &self.out {
+            res += &format!(" {} {}",op,shlex::quote(out));
+        }
+        if let Some(err) = &self.err {
+            res += &format!(" 2{} {}",op,shlex::quote(err));
+        }
+        res
+    }
+}
+
+// --nice: the program's scheduling priority, from -20 (highest) to 19 (lowest)
+#[cfg(unix)]
+pub fn set_priority(builder: &mut Command, nice: i32) -> Result<(),String> {
+    use std::os::unix::process::CommandExt;
+    if ! (-20..=19).contains(&nice) {
+        return Err(format!("--nice must be between -20 and 19, not {}",nice));
+    }
+    if nice < 0 && unsafe { libc::geteuid() } != 0 {
+        return Err("only root can give a program a higher priority (a negative --nice)".into());
+    }
+    unsafe {
+        builder.pre_exec(move || {
+            if libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn set_priority(_builder: &mut Command, _nice: i32) -> Result<(),String> {
+    Err("--nice is only supported on Unix".into())
+}
+
+// a list of CPUs like '0,2-3'
+pub fn parse_cpus(list: &str) -> Result<Vec<usize>,String> {
+    let bad = || format!("--cpus wants a list like '0,2-3', not '{}'",list);
+    let mut cpus = Vec::new();
+    for part in list.split(',').map(str::trim) {
+        let (from,to) = part.split_once('-').unwrap_or((part,part));
+        let from: usize = from.trim().parse().map_err(|_| bad())?;
+        let to: usize = to.trim().parse().map_err(|_| bad())?;
+        if from > to {
+            return Err(bad());
+        }
+        cpus.extend(from..=to);
+    }
+    Ok(cpus)
+}
+
+// --cpus: which CPUs the program may run on
+#[cfg(target_os = "linux")]
+pub fn set_cpus(builder: &mut Command, cpus: &[usize]) -> Result<(),String> {
+    use std::os::unix::process::CommandExt;
+    let max = libc::CPU_SETSIZE as usize;
+    if let Some(cpu) = cpus.iter().find(|&&c| c >= max) {
+        return Err(format!("--cpus: no CPU {}",cpu));
+    }
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_ZERO(&mut set);
+        for &cpu in cpus {
+            libc::CPU_SET(cpu, &mut set);
+        }
+        builder.pre_exec(move || {
+            if libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn set_cpus(_builder: &mut Command, _cpus: &[usize]) -> Result<(),String> {
+    Err("--cpus is only supported on Linux".into())
+}
+
+// the program's exit code for runner's own, as a shell would give it
+pub fn exit_code(status: &ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(sig) = status.signal() {
+            return 128 + sig;
+        }
+    }
+    status.code().unwrap_or(-1)
+}
+
+// the process group of the running program, for the signal handler
+#[cfg(unix)]
+static PROGRAM: AtomicI32 = AtomicI32::new(0);
+
+#[cfg(unix)]
+extern "C" fn forward_signal(sig: libc::c_int) {
+    let pgid = PROGRAM.load(Ordering::SeqCst);
+    if pgid > 0 {
+        unsafe { libc::kill(-pgid, sig); }
+    }
+}
+
+#[cfg(unix)]
+const FORWARDED: [libc::c_int; 4] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP, libc::SIGQUIT];
+
+// Run the program so that signals meant for it get to it, and runner doesn't die
+// leaving it running. It gets its own process group, which has the terminal
+// while it runs: Ctrl-C goes to the program, and if it stops (Ctrl-Z) runner stops
+// too, so the shell gets control. Signals sent to runner itself are passed on.
+// Afterwards runner takes the terminal back, with its settings as they were.
+#[cfg(unix)]
+pub fn run_program(builder: &mut Command) -> io::Result<ExitStatus> {
+    use std::os::unix::process::{CommandExt,ExitStatusExt};
+    unsafe {
+        // only if we are in the foreground
+        let terminal = libc::isatty(0) == 1 && libc::tcgetpgrp(0) == libc::getpgrp();
+        let mut saved: libc::termios = std::mem::zeroed();
+        let terminal = terminal && libc::tcgetattr(0, &mut saved) == 0;
+        builder.pre_exec(move || {
+            libc::setpgid(0, 0);
+            if terminal {
+                // from the background, this needs SIGTTOU ignored
+                libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+                libc::tcsetpgrp(0, libc::getpid());
+                libc::signal(libc::SIGTTOU, libc::SIG_DFL);
+            }
+            Ok(())
+        });
+        let child = builder.spawn()?;
+        let pid = child.id() as libc::pid_t;
+        // either of us may get there first
+        libc::setpgid(pid, pid);
+        PROGRAM.store(pid, Ordering::SeqCst);
+        let old_ttou = libc::signal(libc::SIGTTOU, libc::SIG_IGN);
+        let handler = forward_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        let old: Vec<_> = FORWARDED.iter().map(|&sig| libc::signal(sig, handler)).collect();
+        if terminal {
+            libc::tcsetpgrp(0, pid);
+        }
+        let mut status = 0;
+        loop {
+            if libc::waitpid(pid, &mut status, libc::WUNTRACED) < 0 {
+                if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                break;
+            }
+            if ! libc::WIFSTOPPED(status) {
+                break;
+            }
+            // stopped: so are we, until the shell continues us
+            if terminal {
+                libc::tcsetpgrp(0, libc::getpgrp());
+                libc::tcsetattr(0, libc::TCSADRAIN, &saved);
+            }
+            libc::raise(libc::SIGSTOP);
+            if terminal {
+                libc::tcsetpgrp(0, pid);
+            }
+            libc::kill(-pid, libc::SIGCONT);
+        }
+        PROGRAM.store(0, Ordering::SeqCst);
+        if terminal {
+            libc::tcsetpgrp(0, libc::getpgrp());
+            libc::tcsetattr(0, libc::TCSADRAIN, &saved);
+        }
+        for (&sig,old) in FORWARDED.iter().zip(old) {
+            libc::signal(sig, old);
+        }
+        libc::signal(libc::SIGTTOU, old_ttou);
+        Ok(ExitStatus::from_raw(status))
+    }
+}
+
+#[cfg(not(unix))]
+pub fn run_program(builder: &mut Command) -> io::Result<ExitStatus> {
+    builder.status()
+}
+
+// the text on the clipboard, from whichever tool this platform has
+pub fn paste() -> String {
+    let tools: &[&[&str]] = if cfg!(target_os = "macos") {
+        &[&["pbpaste"]]
+    } else if cfg!(target_os = "windows") {
+        &[&["powershell","-NoProfile","-Command","Get-Clipboard -Raw"]]
+    } else {
+        &[&["wl-paste","--no-newline"], &["xclip","-selection","clipboard","-o"], &["xsel","--clipboard","--output"]]
     };
+    for tool in tools {
+        if let Ok(output) = Command::new(tool[0]).args(&tool[1..]).output() {
+            if output.status.success() {
+                return String::from_utf8_lossy(&output.stdout).to_string();
+            }
+        }
+    }
+    let names: Vec<_> = tools.iter().map(|t| t[0]).collect();
+    es::quit(&format!("cannot read the clipboard (tried {})",names.join(", ")));
+}
+
+// an editor in config.toml is for runner specifically
+pub fn editor() -> String {
+    crate::config::CONFIG.editor.clone().unwrap_or_else(default_editor)
+}
+
+pub fn edit(p: &Path) {
+    let editor = editor();
     if editor == "open" {
         open(p);
     } else {
@@ -0,0 +1,105 @@
+// runner --list shows the programs in the bin directory which 'runner -r' can
+// run again, with how and when they were built and the file they came from.
+// 'runner -r name' finds one by name, from any directory.
+use std::env::consts::EXE_SUFFIX;
+use std::fs;
+use std::path::{Path,PathBuf};
+use std::time::{SystemTime,UNIX_EPOCH};
+
+use crate::cache;
+use crate::gc;
+use crate::strutil;
+
+// 'YYYY-MM-DD HH:MM' in UTC (days to a civil date as in Howard Hinnant's date algorithms)
+pub fn utc_time(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) as i64;
+    let (days,rest) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe/1460 + doe/36524 - doe/146096) / 365;
+    let doy = doe - (365*yoe + yoe/4 - yoe/100);
+    let mp = (5*doy + 2) / 153;
+    let day = doy - (153*mp + 2)/5 + 1;
+    let month = if mp < 10 {mp + 3} else {mp - 9};
+    let year = yoe + era*400 + if month <= 2 {1} else {0};
+    format!("{}-{:02}-{:02} {:02}:{:02}",year,month,day,rest/3600,rest%3600/60)
+}
+
+// the programs themselves, not their sources and other files
+pub fn programs() -> Vec<PathBuf> {
+    let exe = EXE_SUFFIX.trim_start_matches('.');
+    let mut res: Vec<PathBuf> = fs::read_dir(cache::runner_directory().join("bin"))
+        .map(|entries| entries.flatten().map(|e| e.path()).collect())
+        .unwrap_or_default();
+    // on Unix, anything which isn't one of the files which go with a program, so 'a.x' is one
+    res.retain(|p| p.is_file() && match p.extension().and_then(|e| e.to_str()) {
+        Some(e) if ! exe.is_empty() => e == exe,
+        Some(e) => ! gc::BIN_EXTENSIONS.contains(&e),
+        None => exe.is_empty()
+    });
+    res.sort();
+    res
+}
+
+fn program_name(program: &Path) -> String {
+    let name = program.file_name().and_then(|s| s.to_str()).unwrap_or_default();
+    name.strip_suffix(EXE_SUFFIX).unwrap_or(name).to_string()
+}
+
+// the program called this (or 'name.rs', or 'name.exe'). Otherwise the one
+// program whose name starts with it; if there isn't just one, runner quits
+// with the names which are close
+pub fn find(name: &str) -> PathBuf {
+    let name = name.strip_suffix(".rs").or_else(|| name.strip_suffix(".exe")).unwrap_or(name);
+    let programs = programs();
+    if let Some(p) = programs.iter().find(|p| program_name(p) == name) {
+        return p.clone();
+    }
+    let starting: Vec<&PathBuf> = programs.iter().filter(|p| program_name(p).starts_with(name)).collect();
+    if starting.len() == 1 {
+        verbose!("running {}",program_name(starting[0]));
+        return starting[0].clone();
+    }
+    // names which start with it, then those containing it, then those a typo away
+    let typos = (name.chars().count() / 3).max(1);
+    let mut close: Vec<(usize,usize,String)> = programs.iter()
+        .map(|p| program_name(p))
+        .map(|n| {
+            let kind = if n.starts_with(name) {0} else if n.contains(name) {1} else {2};
+            (kind, strutil::edit_distance(name, &n), n)
+        })
+        .filter(|(kind,d,_)| *kind < 2 || *d <= typos)
+        .collect();
+    close.sort();
+    if close.is_empty() {
+        es::quit(&format!("no program '{}' to run: see 'runner --list'",name));
+    }
+    let close: Vec<String> = close.into_iter().map(|(_,_,n)| n).take(10).collect();
+    es::quit(&format!("no program '{}' to run; did you mean {}?",name,close.join(", ")));
+}
+
+pub fn list() {
+    let rows: Vec<[String; 5]> = programs().iter().map(|program| {
+        let meta = fs::metadata(program);
+        let built = meta.as_ref().ok().and_then(|m| m.modified().ok()).map(utc_time).unwrap_or_default();
+        let size = meta.map(|m| m.len()).unwrap_or(0);
+        let mode = cache::recorded_mode(program);
+        // expressions have a mode but no source; older runners left neither
+        let source = match (cache::recorded_source(program),&mode) {
+            (Some(source),_) => source.display().to_string(),
+            (None,Some(_)) => "(expression)".to_string(),
+            (None,None) => "?".to_string(),
+        };
+        [program_name(program), mode.unwrap_or_else(|| "?".into()), built, gc::human_size(size), source]
+    }).collect();
+    if rows.is_empty() {
+        info!("no compiled programs in {}",cache::runner_directory().join("bin").display());
+        return;
+    }
+    let widths: Vec<usize> = (0..4).map(|i| rows.iter().map(|r| r[i].len()).max().unwrap_or(0)).collect();
+    for r in &rows {
+        println!("{:<w0$}  {:<w1$}  {:<w2$}  {:>w3$}  {}",r[0],r[1],r[2],r[3],r[4],
+            w0=widths[0],w1=widths[1],w2=widths[2],w3=widths[3]);
+    }
+}
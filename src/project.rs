@@ -0,0 +1,74 @@
+// --emit-project writes the program runner would compile as a Cargo project,
+// so that tools like rust-analyzer can be pointed at a snippet
+use es::traits::*;
+use std::fs;
+use std::path::Path;
+
+use crate::cache;
+use crate::crate_utils;
+use crate::meta;
+
+// the dependency lines in the static cache's Cargo.toml, by package name
+fn static_cache_dependencies() -> Vec<(String,String)> {
+    let cargo_toml = cache::static_cache_dir().join("Cargo.toml");
+    let body = fs::read_to_string(&cargo_toml).unwrap_or_default();
+    let mut in_deps = false;
+    let mut res = Vec::new();
+    for line in body.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_deps = line == "[dependencies]";
+        } else if in_deps {
+            if let Some(idx) = line.find('=') {
+                res.push((line[0..idx].trim().to_string(), line.to_string()));
+            }
+        }
+    }
+    res
+}
+
+// a crate used by the snippet becomes a dependency like the one in the static cache.
+// Crates which only come in indirectly get the version which was built
+fn dependency(c: &str, deps: &[(String,String)], m: Option<&meta::Meta>) -> Option<String> {
+    let name = crate_utils::proper_crate_name(c);
+    if let Some((_,line)) = deps.iter().find(|(p,_)| crate_utils::proper_crate_name(p) == name) {
+        return Some(line.clone());
+    }
+    let e = m?.get_meta_entry(&name)?;
+    // local crates are not in the Cargo cache: the source is <dir>/src/lib.rs
+    if ! e.path.starts_with(crate_utils::cargo_home()) {
+        if let Some(dir) = e.path.parent().and_then(|p| p.parent()) {
+            return Some(format!("{} = {{path=\"{}\"}}",e.package,dir.display()));
+        }
+    }
+    Some(format!("{} = \"={}\"",e.package,e.version))
+}
+
+pub fn emit_project(dir: &Path, name: &str, code: &str, externs: &[String], edition: &str) {
+    let aliases = cache::get_aliases();
+    let static_cache = cache::static_cache_dir();
+    let m = if meta::Meta::exists(&static_cache) {
+        Some(meta::Meta::new_from_file(&static_cache))
+    } else {
+        None
+    };
+    let deps = static_cache_dependencies();
+
+    let mut cargo_toml = format!("[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"{}\"\n\n[dependencies]\n",
+        name, edition);
+    for c in externs {
+        let c = aliases.get(c).unwrap_or(c);
+        if let Some(line) = dependency(c, &deps, m.as_ref()) {
+            cargo_toml += &line;
+            cargo_toml.push('\n');
+        } else {
+            info!("crate '{}' is not in the static cache",c);
+        }
+    }
+
+    let src = dir.join("src");
+    fs::create_dir_all(&src).or_die("cannot create project directory");
+    fs::write(dir.join("Cargo.toml"),cargo_toml).or_die("cannot write Cargo.toml");
+    fs::write(src.join("main.rs"),code).or_die("cannot write main.rs");
+    info!("wrote project '{}' to {}",name,dir.display());
+}
@@ -0,0 +1,158 @@
+// runner --snapshot keeps the program's output in a .snap file next to the snippet
+// the first time it runs, and afterwards checks the output against it, showing a
+// diff if they differ. --snapshot-update takes the new output as the snapshot.
+// So a directory of example snippets is also a regression suite.
+use es::traits::*;
+use std::fs;
+use std::io::{self,Write};
+use std::path::Path;
+use std::process::{Command,Stdio};
+
+use crate::platform;
+
+// lines on either side of a change
+const CONTEXT: usize = 2;
+
+#[derive(Clone,Copy,PartialEq)]
+enum Edit {
+    Same,
+    Removed,
+    Added,
+}
+
+// the longest common subsequence of lines, as the edits which turn old into new
+fn edits(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let (n,m) = (old.len(),new.len());
+    let mut lcs = vec![vec![0u32; m+1]; n+1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i+1][j+1] + 1
+            } else {
+                lcs[i+1][j].max(lcs[i][j+1])
+            };
+        }
+    }
+    let (mut i, mut j) = (0,0);
+    let mut res = Vec::new();
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            res.push(Edit::Same);
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i+1][j] >= lcs[i][j+1]) {
+            res.push(Edit::Removed);
+            i += 1;
+        } else {
+            res.push(Edit::Added);
+            j += 1;
+        }
+    }
+    res
+}
+
+// like 'diff -u', with line numbers in the @@ lines
+pub fn diff(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let edits = edits(&old, &new);
+    // where each edit is in the old and new lines
+    let mut positions = Vec::new();
+    let (mut i, mut j) = (0,0);
+    for e in &edits {
+        positions.push((i,j));
+        match e {
+            Edit::Same => { i += 1; j += 1; },
+            Edit::Removed => i += 1,
+            Edit::Added => j += 1,
+        }
+    }
+    let mut res = String::new();
+    let mut k = 0;
+    while k < edits.len() {
+        if edits[k] == Edit::Same {
+            k += 1;
+            continue;
+        }
+        // a hunk runs until there are more than two contexts' worth of unchanged lines
+        let start = k.saturating_sub(CONTEXT);
+        let mut end = k;
+        while end < edits.len() {
+            let same = edits[end..].iter().take_while(|e| **e == Edit::Same).count();
+            if same == 0 {
+                end += 1;
+            } else if end + same < edits.len() && same <= 2*CONTEXT {
+                end += same;
+            } else {
+                end = (end + same.min(CONTEXT)).min(edits.len());
+                break;
+            }
+        }
+        let hunk = &edits[start..end];
+        let (oi,nj) = positions[start];
+        let old_len = hunk.iter().filter(|e| **e != Edit::Added).count();
+        let new_len = hunk.iter().filter(|e| **e != Edit::Removed).count();
+        res += &format!("@@ -{},{} +{},{} @@\n",oi+1,old_len,nj+1,new_len);
+        for (e,&(i,j)) in hunk.iter().zip(&positions[start..end]) {
+            match e {
+                Edit::Same => res += &format!(" {}\n",old[i]),
+                Edit::Removed => res += &format!("-{}\n",old[i]),
+                Edit::Added => res += &format!("+{}\n",new[j]),
+            }
+        }
+        k = end;
+    }
+    res
+}
+
+// the program's output as it was written, or its exit code if it failed
+fn capture_bytes(builder: &mut Command) -> Result<Vec<u8>,i32> {
+    builder.stdin(Stdio::inherit()).stdout(Stdio::piped()).stderr(Stdio::inherit());
+    let output = builder.output().or_die("can't run program");
+    if ! output.status.success() {
+        io::stdout().write_all(&output.stdout).or_die("i/o?");
+        return Err(platform::exit_code(&output.status));
+    }
+    Ok(output.stdout)
+}
+
+pub fn capture(builder: &mut Command) -> Result<String,i32> {
+    capture_bytes(builder).map(|out| String::from_utf8_lossy(&out).to_string())
+}
+
+// run the program, and check its output against the snapshot (or make it).
+// Returns the exit code
+pub fn run(builder: &mut Command, snap: &Path, update: bool) -> i32 {
+    // compared as bytes, so binary output can be a snapshot as well
+    let actual = match capture_bytes(builder) {
+        Ok(output) => output,
+        Err(code) => return code
+    };
+    let write = |verb: &str| {
+        fs::write(snap, &actual).or_then_die(|e| format!("cannot write {}: {}",snap.display(),e));
+        info!("{} {}",verb,snap.display());
+    };
+    if ! snap.exists() {
+        write("created");
+        return 0;
+    }
+    let expected = fs::read(snap).or_then_die(|e| format!("cannot read {}: {}",snap.display(),e));
+    if expected == actual {
+        info!("output matches {}",snap.display());
+        return 0;
+    }
+    if update {
+        write("updated");
+        return 0;
+    }
+    let (expected,actual) = (String::from_utf8_lossy(&expected),String::from_utf8_lossy(&actual));
+    println!("--- {}\n+++ output",snap.display());
+    print!("{}",diff(&expected, &actual));
+    if expected == actual {
+        println!("(only bytes which aren't UTF-8 differ)");
+    } else if expected.lines().eq(actual.lines()) {
+        println!("(only the line endings differ)");
+    }
+    info!("output differs from {} (--snapshot-update accepts it)",snap.display());
+    1
+}
@@ -0,0 +1,158 @@
+// the snippet library - named snippets kept in ~/.cargo/.runner/snippets
+// which can be run later with --load
+use es::traits::*;
+use std::fs;
+use std::path::PathBuf;
+use std::collections::HashMap;
+
+use crate::cache;
+use crate::crate_utils;
+
+pub fn snippet_directory() -> PathBuf {
+    cache::runner_directory().join("snippets")
+}
+
+fn check_name(name: &str) {
+    if name.is_empty() || ! crate_utils::plain_name(name) {
+        es::quit(&format!("bad snippet name {:?}: use a plain name like 'hexdump'",name));
+    }
+}
+
+pub fn snippet_path(name: &str) -> PathBuf {
+    check_name(name);
+    snippet_directory().join(name).with_extension("rs")
+}
+
+// path of an existing snippet, quitting if it isn't in the library
+pub fn find_snippet(name: &str) -> PathBuf {
+    let path = snippet_path(name);
+    if ! path.is_file() {
+        es::quit(&format!("no snippet {:?} in library: see --list-snippets",name));
+    }
+    path
+}
+
+// any runner arguments needed to run the snippet go into a first arg comment
+pub fn save_snippet(name: &str, code: &str, arg_comment: &str) -> PathBuf {
+    let dir = snippet_directory();
+    if ! dir.is_dir() {
+        fs::create_dir_all(&dir).or_die("cannot create snippet directory");
+    }
+    let path = snippet_path(name);
+    let contents = if arg_comment.is_empty() {
+        code.to_string()
+    } else {
+        format!("//: {}\n{}",arg_comment,code)
+    };
+    fs::write(&path,contents).or_die("cannot write snippet");
+    path
+}
+
+// tags are kept in the form name=tag1,tag2 - one line per snippet
+fn tags_file() -> PathBuf {
+    snippet_directory().join("tags")
+}
+
+pub fn get_tags() -> HashMap<String,Vec<String>> {
+    let tags = tags_file();
+    if ! tags.is_file() { return HashMap::new(); }
+    let contents = fs::read_to_string(&tags).or_die("cannot read snippet tags");
+    contents.lines()
+        .filter_map(|s| s.split_at_delim('=').trim())
+        .map(|(name,tags): (String,String)| {
+            let tags = tags.split(',').map(|t| t.trim().to_string()).filter(|t| ! t.is_empty()).collect();
+            (name,tags)
+        })
+        .to_map()
+}
+
+pub fn set_tags(name: &str, tags: Vec<String>) {
+    let mut all_tags = get_tags();
+    all_tags.insert(name.into(), tags);
+    let mut names: Vec<_> = all_tags.keys().cloned().collect();
+    names.sort();
+    let mut contents = String::new();
+    for name in names {
+        let tags = &all_tags[&name];
+        if ! tags.is_empty() {
+            contents += &format!("{}={}\n",name,tags.join(","));
+        }
+    }
+    fs::write(tags_file(),contents).or_die("cannot write snippet tags");
+}
+
+// first line of actual code, to give an idea of what a snippet does
+pub fn preview(code: &str) -> String {
+    let line = code.lines()
+        .map(|l| l.trim())
+        .find(|l| ! l.is_empty() && ! l.starts_with("//"))
+        .unwrap_or("");
+    if line.chars().count() > 60 {
+        format!("{}...",line.chars().take(57).collect::<String>())
+    } else {
+        line.into()
+    }
+}
+
+pub fn describe(name: &str, tags: Option<&Vec<String>>) -> String {
+    match tags {
+        Some(tags) if ! tags.is_empty() => format!("{} [{}]",name,tags.join(",")),
+        _ => name.into()
+    }
+}
+
+// case-insensitive search over snippet names, tags and source
+pub fn search_snippets(query: &str) {
+    let query = query.to_lowercase();
+    let tags = get_tags();
+    let mut found = false;
+    for name in snippet_names() {
+        let code = fs::read_to_string(snippet_path(&name)).or_die("cannot read snippet");
+        let snippet_tags = tags.get(&name);
+        let matches = name.to_lowercase().contains(&query)
+            || snippet_tags.map(|t| t.iter().any(|t| t.to_lowercase().contains(&query))).unwrap_or(false)
+            || code.to_lowercase().contains(&query);
+        if matches {
+            found = true;
+            println!("{}\n    {}",describe(&name,snippet_tags),preview(&code));
+        }
+    }
+    if ! found {
+        println!("no snippets match {:?}",query);
+    }
+}
+
+// the snippet, and its tags
+pub fn remove_snippet(name: &str) {
+    let path = find_snippet(name);
+    fs::remove_file(&path).or_die("cannot remove snippet");
+    if get_tags().contains_key(name) {
+        set_tags(name, Vec::new());
+    }
+}
+
+pub fn snippet_names() -> Vec<String> {
+    let dir = snippet_directory();
+    if ! dir.is_dir() {
+        return Vec::new();
+    }
+    let mut names: Vec<String> = fs::read_dir(&dir).or_die("cannot read snippet directory")
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().map(|e| e == "rs").unwrap_or(false))
+        .map(|p| crate_utils::path_file_name(&p.with_extension("")))
+        .collect();
+    names.sort();
+    names
+}
+
+pub fn list_snippets() {
+    let names = snippet_names();
+    if names.is_empty() {
+        println!("no snippets saved yet: use --save NAME");
+    }
+    let tags = get_tags();
+    for name in names {
+        println!("{}",describe(&name,tags.get(&name)));
+    }
+}
@@ -1,8 +1,33 @@
+use crate::crate_utils;
+
 pub struct State {
     pub build_static: bool,
     pub optimize: bool,
     pub exe: bool,
     pub edition: String,
+    // finer control of the build, like a Cargo profile
+    pub opt_level: Option<String>,
+    pub lto: bool,
+    pub codegen_units: Option<i32>,
+    pub panic: Option<String>,
+    pub strip: Option<String>,
+    pub small: bool,
+    pub debuginfo: Option<String>,
+    pub split_debuginfo: Option<String>,
+    // --unstable: -Z options, for a nightly rustc
+    pub unstable: Vec<String>,
+    // --rpath: dynamic programs know where their libraries are
+    pub rpath: bool,
+    // the C library is linked in as well, for a container whose own may not match
+    pub crt_static: bool,
+}
+
+fn one_of(args: &lapp::Args, flag: &str, allowed: &[&str]) -> Option<String> {
+    let value = args.get_string_result(flag).ok()?;
+    if ! allowed.contains(&value.as_str()) {
+        args.quit(&format!("--{} must be one of {}, not '{}'",flag,allowed.join(", "),value));
+    }
+    Some(value)
 }
 
 impl State {
@@ -12,17 +37,98 @@ impl State {
             optimize: optimized,
             exe: true,
             edition: edition.into(),
+            opt_level: None,
+            lto: false,
+            codegen_units: None,
+            panic: None,
+            strip: None,
+            small: false,
+            debuginfo: None,
+            split_debuginfo: None,
+            unstable: Vec::new(),
+            rpath: false,
+            crt_static: false,
         }
     }
 
     pub fn dll(optimized: bool, edition: &str) -> State {
         State {
             build_static: false,
-            optimize: optimized,
             exe: false,
-            edition: edition.into(),
+            ..State::exe(false, optimized, edition)
         }
     }
 
-}
+    // --opt-level, --lto, --codegen-units, --panic and --strip.
+    // --small makes the defaults as small as possible
+    pub fn with_profile(mut self, args: &lapp::Args) -> State {
+        self.small = args.get_bool("small");
+        let is_small = self.small;
+        let small = |value: &str| if is_small {Some(value.to_string())} else {None};
+        self.opt_level = one_of(args, "opt-level", &["0","1","2","3","s","z"]).or_else(|| small("z"));
+        self.lto = args.get_bool("lto") || self.small;
+        self.codegen_units = args.get_integer_result("codegen-units").ok();
+        self.panic = one_of(args, "panic", &["abort","unwind"]).or_else(|| small("abort"));
+        self.strip = one_of(args, "strip", &["none","debuginfo","symbols"]).or_else(|| small("symbols"));
+        self.debuginfo = one_of(args, "debuginfo", &["0","1","2"]);
+        self.split_debuginfo = one_of(args, "split-debuginfo", &["off","packed","unpacked"]);
+        // as in '--unstable polonius' or '--unstable -Zprint-type-sizes'
+        self.unstable = args.get_strings("unstable").into_iter()
+            .map(|z| z.trim_start_matches('-').trim_start_matches('Z').to_string())
+            .collect();
+        if ! self.unstable.is_empty() && ! crate_utils::is_nightly() {
+            args.quit("--unstable needs a nightly toolchain (like 'rustup run nightly runner ...')");
+        }
+        // Windows only looks on the PATH
+        self.rpath = args.get_bool("rpath") && ! cfg!(windows);
+        self
+    }
+
+    // debug information is only there by default for unoptimized static builds
+    fn debuginfo(&self) -> &str {
+        self.debuginfo.as_deref().unwrap_or(if self.build_static && ! self.optimize {"2"} else {"0"})
+    }
+
+    // the -C options for rustc which these become
+    pub fn codegen_options(&self) -> Vec<String> {
+        let mut res = vec![format!("debuginfo={}",self.debuginfo())];
+        if let Some(ref split) = self.split_debuginfo {
+            res.push(format!("split-debuginfo={}",split));
+        }
+        if let Some(ref level) = self.opt_level {
+            res.push(format!("opt-level={}",level));
+        }
+        if self.lto {
+            res.push("lto".to_string());
+        }
+        if let Some(units) = self.codegen_units {
+            res.push(format!("codegen-units={}",units));
+        }
+        if let Some(ref panic) = self.panic {
+            res.push(format!("panic={}",panic));
+        }
+        if let Some(ref strip) = self.strip {
+            res.push(format!("strip={}",strip));
+        }
+        if self.crt_static {
+            res.push("target-feature=+crt-static".to_string());
+        }
+        res
+    }
 
+    // how the program is built, for --list
+    pub fn mode(&self) -> String {
+        let mut mode = if self.build_static {"static"} else {"dynamic"}.to_string();
+        if self.optimize {
+            mode += " optimized";
+        }
+        mode
+    }
+
+    // builds which differ in any of these must be kept apart
+    pub fn describe(&self) -> String {
+        format!("{} {} {} {} {}",self.build_static,self.optimize,self.edition,self.codegen_options().join(" "),self.unstable.join(" "))
+            + if self.rpath {" rpath"} else {""}
+    }
+
+}
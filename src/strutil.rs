@@ -35,3 +35,184 @@ pub fn split(txt: &str, delim: char) -> (&str,&str) {
     }
 }
 
+// like 'a', '\n', '\'' or '\u{1F600}', at the start of the text: one character,
+// or one escape, and then the closing quote
+fn char_literal_len(chars: &[char]) -> Option<usize> {
+    let len = match chars {
+        ['\'', '\\', 'u', '{', rest @ ..] => rest.iter().take(7).position(|&c| c == '}')? + 5,
+        ['\'', '\\', 'x', _, _, ..] => 5,
+        ['\'', '\\', _, ..] => 3,
+        ['\'', c, ..] if *c != '\'' => 2,
+        _ => return None
+    };
+    (chars.get(len) == Some(&'\'')).then_some(len + 1)
+}
+
+// a lifetime like 'a or 'static, rather than the start of a string. After '&' or '<'
+// it always is, and after '+' or ':' (as in 'T: 'a + 'static'). Otherwise it is
+// if no quote could end it as a string
+fn is_lifetime(chars: &[char], i: usize) -> bool {
+    let ident = |c: &char| c.is_alphanumeric() || *c == '_';
+    let name = chars[i+1..].iter().take_while(|c| ident(c)).count();
+    if name == 0 || chars.get(i + 1 + name) == Some(&'\'') {
+        return false;
+    }
+    match chars[..i].iter().rev().find(|c| ! c.is_whitespace()) {
+        Some('&') | Some('<') | Some('+') | Some(':') => true,
+        _ => ! chars[i+1..].contains(&'\'')
+    }
+}
+
+// single-quoted strings become double-quoted, for shells where double quotes are
+// hard to get into an argument (i.e. Windows). Char literals like 'a', lifetimes
+// like &'a and anything already in a double-quoted string are left alone
+pub fn rewrite_quotes(text: &str) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let mut res = String::new();
+    // the quote which ends the string we're in
+    let mut in_string = None;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if let Some(end) = in_string {
+            if c == '\\' && i + 1 < chars.len() {
+                res.push(c);
+                res.push(chars[i+1]);
+                i += 2;
+                continue;
+            }
+            if c == end {
+                in_string = None;
+                res.push('"');
+            } else if c == '"' {
+                // a double quote in a single-quoted string
+                res.push_str("\\\"");
+            } else {
+                res.push(c);
+            }
+        } else if c == '"' {
+            in_string = Some('"');
+            res.push(c);
+        } else if c == '\'' {
+            if let Some(len) = char_literal_len(&chars[i..]) {
+                res.extend(&chars[i..i+len]);
+                i += len;
+                continue;
+            }
+            if is_lifetime(&chars, i) {
+                res.push(c);
+            } else {
+                in_string = Some('\'');
+                res.push('"');
+            }
+        } else {
+            res.push(c);
+        }
+        i += 1;
+    }
+    res
+}
+
+// the length of the string (perhaps raw or bytes) starting the text, with its quotes
+fn string_len(chars: &[char]) -> Option<usize> {
+    let start = if chars.first() == Some(&'b') {1} else {0};
+    if chars.get(start) == Some(&'r') {
+        let hashes = chars[start+1..].iter().take_while(|&&c| c == '#').count();
+        let open = start + 1 + hashes;
+        if chars.get(open) != Some(&'"') {
+            return None;
+        }
+        let end = (open+1..chars.len()).find(|&k| chars[k] == '"'
+            && chars[k+1..].iter().take(hashes).filter(|&&c| c == '#').count() == hashes)?;
+        return Some(end + 1 + hashes);
+    }
+    if chars.get(start) != Some(&'"') {
+        return None;
+    }
+    let mut k = start + 1;
+    while k < chars.len() {
+        match chars[k] {
+            '\\' => k += 1,
+            '"' => return Some(k + 1),
+            _ => {}
+        }
+        k += 1;
+    }
+    None
+}
+
+// the code with strings, characters and (if asked) comments blanked out, byte for
+// byte, so that anything found in it is at the same place in the code. Line breaks
+// stay where they are
+pub fn blank_literals(code: &str, comments: bool) -> String {
+    let chars: Vec<char> = code.chars().collect();
+    let ident = |k: usize| k > 0 && (chars[k-1].is_alphanumeric() || chars[k-1] == '_');
+    let mut res = String::with_capacity(code.len());
+    let mut i = 0;
+    while i < chars.len() {
+        let (c,next) = (chars[i], chars.get(i+1).copied());
+        let (len,blank) = if c == '/' && next == Some('/') {
+            (chars[i..].iter().position(|&c| c == '\n').unwrap_or(chars.len() - i), comments)
+        } else if c == '/' && next == Some('*') {
+            // which may nest
+            let (mut depth, mut k) = (0, i);
+            while k < chars.len() {
+                match (chars[k], chars.get(k+1)) {
+                    ('/', Some('*')) => { depth += 1; k += 1; },
+                    ('*', Some('/')) => { depth -= 1; k += 1; },
+                    _ => {}
+                }
+                k += 1;
+                if depth == 0 {
+                    break;
+                }
+            }
+            (k - i, comments)
+        } else if let Some(len) = string_len(&chars[i..]).filter(|_| ! ident(i)) {
+            (len, true)
+        } else if let Some(len) = char_literal_len(&chars[i..]) {
+            (len, true)
+        } else if let Some(len) = char_literal_len(&chars[i+1..]).filter(|_| c == 'b' && ! ident(i)) {
+            (len + 1, true)
+        } else {
+            (1, false)
+        };
+        for &c in &chars[i..i+len] {
+            if blank && c != '\n' {
+                res.push_str(&" ".repeat(c.len_utf8()));
+            } else {
+                res.push(c);
+            }
+        }
+        i += len;
+    }
+    res
+}
+
+// a stable hash of some strings (64-bit FNV-1a) as hex, used to make up file names
+pub fn content_hash(parts: &[&str]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for part in parts {
+        // separator byte, so that ["ab","c"] and ["a","bc"] differ
+        for b in part.bytes().chain(Some(0)) {
+            hash ^= b as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    format!("{:016x}",hash)
+}
+
+// how many single-character edits turn one string into the other (Levenshtein)
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i,ca) in a.chars().enumerate() {
+        let mut row = vec![i+1];
+        for (j,cb) in b.iter().enumerate() {
+            let cost = if ca == *cb {0} else {1};
+            row.push((prev[j] + cost).min(prev[j+1] + 1).min(row[j] + 1));
+        }
+        prev = row;
+    }
+    prev[b.len()]
+}
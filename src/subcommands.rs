@@ -0,0 +1,81 @@
+// Subcommands like 'runner cache add regex' are another way of writing the flags
+// ('runner --add regex'), which keep working. They must come first, and are turned
+// into flags before anything looks at the command line.
+use std::path::Path;
+
+const CACHE_COMMANDS: &[(&str,&str)] = &[
+    ("add","--add"),
+    ("remove","--remove"),
+    ("build","--build"),
+    ("update","--update"),
+    ("list","--crates"),
+    ("why","--why"),
+    ("edit","--edit"),
+    ("clean","--cleanup"),
+    ("stats","--cache-stats"),
+    ("gc","--gc"),
+    ("vendor","--vendor"),
+    ("export","--export-cache"),
+    ("import","--import-cache"),
+];
+
+const CRATE_COMMANDS: &[(&str,&str)] = &[
+    ("path","--crate-path"),
+    ("compile","--compile"),
+];
+
+fn names(commands: &[(&str,&str)]) -> String {
+    commands.iter().map(|(name,_)| *name).collect::<Vec<_>>().join(", ")
+}
+
+// the second word, as in 'runner crate path regex'
+fn lookup(commands: &[(&str,&str)], group: &str, word: Option<&String>) -> String {
+    let flag = word.and_then(|w| commands.iter().find(|(name,_)| name == w)).map(|(_,flag)| flag);
+    match flag {
+        Some(flag) => flag.to_string(),
+        None => es::quit(&format!("'runner {}' needs one of {}",group,names(commands)))
+    }
+}
+
+// the command line (with the program name first), with any subcommand as flags
+pub fn expand(args: Vec<String>) -> Vec<String> {
+    let command = match args.get(1) {
+        Some(command) => command.clone(),
+        None => return args
+    };
+    // a file called 'run' is still a program, and a script's arguments are its own
+    if Path::new(&command).exists() || args.iter().any(|a| a == "--script") {
+        return args;
+    }
+    let mut rest = args[2..].to_vec();
+    let flags = match command.as_str() {
+        "run" => Vec::new(),
+        "eval" => vec!["-e".to_string()],
+        "doc" => vec!["--doc".to_string()],
+        "crate" => {
+            let flag = lookup(CRATE_COMMANDS, "crate", rest.first());
+            rest.remove(0);
+            vec![flag]
+        },
+        "cache" => {
+            let flag = lookup(CACHE_COMMANDS, "cache", rest.first());
+            rest.remove(0);
+            if flag == "--add" {
+                // the crates up to the first flag, as one '--add "a b"'
+                let crates = rest.iter().take_while(|a| ! a.starts_with('-')).count();
+                let crates: Vec<String> = rest.drain(0..crates).collect();
+                if crates.is_empty() {
+                    es::quit("'runner cache add' needs the crates to add");
+                }
+                vec![flag, crates.join(" ")]
+            } else {
+                vec![flag]
+            }
+        },
+        _ => return args
+    };
+    let mut res = vec![args[0].clone()];
+    res.extend(flags);
+    res.extend(rest);
+    res
+}
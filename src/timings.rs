@@ -0,0 +1,94 @@
+// --timings shows where the time went: runner itself (working out what to do,
+// and generating the program), rustc, and the program. With --timings-json,
+// the same as a JSON object.
+// --repeat runs the program several times and summarizes how long it took.
+use es::traits::*;
+use std::process;
+use std::sync::Mutex;
+use std::time::{Duration,Instant};
+use crate::platform;
+
+struct Timings {
+    start: Instant,
+    last: Instant,
+    phases: Vec<(&'static str,Duration)>,
+    // None if not wanted, otherwise whether as JSON
+    json: Option<bool>,
+}
+
+static TIMINGS: Mutex<Option<Timings>> = Mutex::new(None);
+
+pub fn start() {
+    let now = Instant::now();
+    *TIMINGS.lock().unwrap() = Some(Timings { start: now, last: now, phases: Vec::new(), json: None });
+}
+
+pub fn enable(text: bool, json: bool) {
+    if let Some(t) = TIMINGS.lock().unwrap().as_mut() {
+        if text || json {
+            t.json = Some(json);
+        }
+    }
+}
+
+// the phase which has just finished
+pub fn phase(name: &'static str) {
+    if let Some(t) = TIMINGS.lock().unwrap().as_mut() {
+        let now = Instant::now();
+        t.phases.push((name, now - t.last));
+        t.last = now;
+    }
+}
+
+// on stderr, so that the program's output is left alone
+pub fn report() {
+    let guard = TIMINGS.lock().unwrap();
+    let t = match guard.as_ref() {
+        Some(t) => t,
+        None => return
+    };
+    let total = ("total", t.last - t.start);
+    match t.json {
+        Some(true) => {
+            let mut doc = json::JsonValue::new_object();
+            for (name,d) in t.phases.iter().chain(Some(&total)) {
+                doc[*name] = d.as_secs_f64().into();
+            }
+            eprintln!("{}",doc.dump());
+        },
+        Some(false) => {
+            for (name,d) in t.phases.iter().chain(Some(&total)) {
+                eprintln!("{:>8} {:8.3}s",name,d.as_secs_f64());
+            }
+        },
+        None => {}
+    }
+}
+
+pub fn millis(d: f64) -> String {
+    format!("{:.3}ms",d * 1000.0)
+}
+
+// run the program warmup times without timing it, and then runs times.
+// Returns the exit code, which is the first failure if any
+pub fn repeat(builder: &mut process::Command, runs: usize, warmup: usize) -> i32 {
+    let mut times = Vec::new();
+    for i in 0..warmup+runs {
+        let start = Instant::now();
+        let status = platform::run_program(builder).or_die("can't run program");
+        if ! status.success() {
+            return platform::exit_code(&status);
+        }
+        if i >= warmup {
+            times.push(start.elapsed().as_secs_f64());
+        }
+    }
+    let n = times.len() as f64;
+    let mean = times.iter().sum::<f64>() / n;
+    let stddev = (times.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / n).sqrt();
+    let min = times.iter().cloned().fold(f64::INFINITY, f64::min);
+    let max = times.iter().cloned().fold(0.0, f64::max);
+    eprintln!("{:>6} {:>12} {:>12} {:>12} {:>12}","runs","min","mean","max","stddev");
+    eprintln!("{:>6} {:>12} {:>12} {:>12} {:>12}",runs,millis(min),millis(mean),millis(max),millis(stddev));
+    0
+}
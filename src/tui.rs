@@ -0,0 +1,402 @@
+// runner --tui shows what --crates, --list-snippets and --list show, one tab each,
+// and acts on the selected line: updating, removing or opening the docs of a crate,
+// running, editing or removing a snippet, and re-running or removing a program.
+// The actions run runner itself with the flags, on the ordinary screen, so their
+// output is the usual one. Only on Unix, since it sets up the terminal with termios.
+#[cfg(unix)]
+mod unix {
+    use es::traits::*;
+    use std::io::{self,Read,Write};
+    use std::path::PathBuf;
+    use std::process::Command;
+    use std::{env,fs,mem};
+
+    use crate::{cache,gc,meta,platform,programs,snippets};
+
+    struct Terminal {
+        saved: libc::termios,
+    }
+
+    impl Terminal {
+        fn new() -> Terminal {
+            let mut saved: libc::termios = unsafe { mem::zeroed() };
+            if unsafe { libc::tcgetattr(libc::STDIN_FILENO, &mut saved) } != 0 {
+                es::quit("--tui needs a terminal");
+            }
+            let term = Terminal { saved };
+            term.resume();
+            term
+        }
+
+        // keys come as they are typed, without echo (Ctrl-C is a key as well)
+        fn raw(&self) {
+            let mut raw = self.saved;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO | libc::ISIG);
+            raw.c_cc[libc::VMIN] = 1;
+            raw.c_cc[libc::VTIME] = 0;
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &raw); }
+        }
+
+        // the alternate screen, without a cursor
+        fn resume(&self) {
+            self.raw();
+            print!("\x1b[?1049h\x1b[?25l");
+            io::stdout().flush().ok();
+        }
+
+        // back to the ordinary screen, as it was
+        fn suspend(&self) {
+            print!("\x1b[?25h\x1b[?1049l");
+            io::stdout().flush().ok();
+            unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSAFLUSH, &self.saved); }
+        }
+
+        // rows and columns
+        fn size(&self) -> (usize,usize) {
+            let mut ws: libc::winsize = unsafe { mem::zeroed() };
+            if unsafe { libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut ws) } == 0 && ws.ws_row > 0 {
+                (ws.ws_row as usize, ws.ws_col as usize)
+            } else {
+                (24,80)
+            }
+        }
+    }
+
+    impl Drop for Terminal {
+        fn drop(&mut self) {
+            self.suspend();
+        }
+    }
+
+    #[derive(PartialEq)]
+    enum Key {
+        Up,
+        Down,
+        Left,
+        Right,
+        PageUp,
+        PageDown,
+        Tab,
+        Enter,
+        Quit,
+        Char(char),
+        Other,
+    }
+
+    fn read_key() -> Key {
+        let mut buf = [0u8; 8];
+        let n = match io::stdin().read(&mut buf) {
+            Ok(0) | Err(_) => return Key::Quit,
+            Ok(n) => n
+        };
+        match &buf[0..n] {
+            b"\x1b[A" | b"\x1bOA" => Key::Up,
+            b"\x1b[B" | b"\x1bOB" => Key::Down,
+            b"\x1b[C" | b"\x1bOC" => Key::Right,
+            b"\x1b[D" | b"\x1bOD" => Key::Left,
+            b"\x1b[5~" => Key::PageUp,
+            b"\x1b[6~" => Key::PageDown,
+            b"\t" => Key::Tab,
+            b"\r" | b"\n" => Key::Enter,
+            // Escape, Ctrl-C and Ctrl-D
+            b"\x1b" | b"\x03" | b"\x04" => Key::Quit,
+            [c] if c.is_ascii_graphic() || *c == b' ' => Key::Char(*c as char),
+            _ => Key::Other
+        }
+    }
+
+    #[derive(Clone,Copy,PartialEq)]
+    enum Tab {
+        Crates,
+        Snippets,
+        Programs,
+    }
+
+    const TABS: [Tab; 3] = [Tab::Crates, Tab::Snippets, Tab::Programs];
+
+    impl Tab {
+        fn title(self) -> &'static str {
+            match self {
+                Tab::Crates => "Crates",
+                Tab::Snippets => "Snippets",
+                Tab::Programs => "Programs",
+            }
+        }
+
+        fn keys(self) -> &'static str {
+            match self {
+                Tab::Crates => "d docs  u update  x remove",
+                Tab::Snippets => "enter run  e edit  x remove",
+                Tab::Programs => "enter run again  x remove",
+            }
+        }
+    }
+
+    // a line in a tab, and what it's about: a package, snippet name or program
+    struct Row {
+        key: String,
+        text: String,
+        detail: String,
+    }
+
+    fn crate_rows() -> Vec<Row> {
+        if ! meta::Meta::exists(&cache::static_cache_dir()) {
+            return Vec::new();
+        }
+        let m = cache::get_metadata();
+        let mut rows: Vec<Row> = m.entries().iter().map(|e| {
+            let mut text = format!("{} {}",e.package,e.version);
+            if e.crate_name != e.package {
+                text += &format!(" (crate {})",e.crate_name);
+            }
+            let features = if e.features.is_empty() {"no features"} else {&e.features};
+            let mut detail = format!("features: {}",features);
+            if ! e.edition.is_empty() {
+                detail += &format!("  edition {}",e.edition);
+            }
+            Row { key: e.package.clone(), text, detail }
+        }).collect();
+        rows.sort_by(|a,b| a.text.cmp(&b.text));
+        rows
+    }
+
+    fn snippet_rows() -> Vec<Row> {
+        let tags = snippets::get_tags();
+        snippets::snippet_names().into_iter().map(|name| {
+            let code = fs::read_to_string(snippets::snippet_path(&name)).unwrap_or_default();
+            Row {
+                text: snippets::describe(&name, tags.get(&name)),
+                detail: snippets::preview(&code),
+                key: name,
+            }
+        }).collect()
+    }
+
+    // the programs most recently built first, which stand in for recent runs
+    fn program_rows() -> Vec<Row> {
+        let mut programs: Vec<(std::time::SystemTime,PathBuf)> = programs::programs().into_iter()
+            .map(|p| (fs::metadata(&p).and_then(|m| m.modified()).unwrap_or(std::time::UNIX_EPOCH), p))
+            .collect();
+        programs.sort_by_key(|p| std::cmp::Reverse(p.0));
+        programs.into_iter().map(|(built,program)| {
+            let name = program.file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_string();
+            let mode = cache::recorded_mode(&program);
+            // as with --list, older runners left no record
+            let source = match (cache::recorded_source(&program),&mode) {
+                (Some(source),_) => source.display().to_string(),
+                (None,Some(_)) => "(expression)".to_string(),
+                (None,None) => "?".to_string(),
+            };
+            let mode = mode.unwrap_or_else(|| "?".into());
+            Row {
+                text: format!("{}  {}  {}",name,programs::utc_time(built),mode),
+                detail: source,
+                key: program.display().to_string(),
+            }
+        }).collect()
+    }
+
+    fn rows(tab: Tab) -> Vec<Row> {
+        match tab {
+            Tab::Crates => crate_rows(),
+            Tab::Snippets => snippet_rows(),
+            Tab::Programs => program_rows(),
+        }
+    }
+
+    fn clip(s: &str, width: usize) -> String {
+        s.chars().take(width).collect()
+    }
+
+    struct Tui {
+        term: Terminal,
+        tab: Tab,
+        rows: Vec<Row>,
+        selected: usize,
+        top: usize,
+        message: String,
+    }
+
+    impl Tui {
+        fn select_tab(&mut self, tab: Tab) {
+            self.tab = tab;
+            self.reload();
+            self.selected = 0;
+            self.top = 0;
+        }
+
+        fn reload(&mut self) {
+            self.rows = rows(self.tab);
+            self.selected = self.selected.min(self.rows.len().saturating_sub(1));
+        }
+
+        // the tabs, the rows which fit, the selected row's detail and the keys
+        fn draw(&mut self) {
+            let (height,width) = self.term.size();
+            let list_height = height.saturating_sub(5).max(1);
+            if self.selected < self.top {
+                self.top = self.selected;
+            } else if self.selected >= self.top + list_height {
+                self.top = self.selected + 1 - list_height;
+            }
+            let mut out = String::from("\x1b[H\x1b[2J");
+            let mut tabs = String::new();
+            for tab in &TABS {
+                if *tab == self.tab {
+                    tabs += &format!("\x1b[7m {} \x1b[0m ",tab.title());
+                } else {
+                    tabs += &format!(" {}  ",tab.title());
+                }
+            }
+            out += &format!("{}\r\n\r\n",tabs);
+            if self.rows.is_empty() {
+                let empty = match self.tab {
+                    Tab::Crates => "no static cache (runner --add <crate> makes one)",
+                    Tab::Snippets => "no snippets (runner --save <name> keeps one)",
+                    Tab::Programs => "no compiled programs",
+                };
+                out += &format!("  {}\r\n",empty);
+            }
+            for (i,row) in self.rows.iter().enumerate().skip(self.top).take(list_height) {
+                let line = clip(&row.text, width.saturating_sub(2));
+                if i == self.selected {
+                    out += &format!("\x1b[7m> {}\x1b[0m\r\n",line);
+                } else {
+                    out += &format!("  {}\r\n",line);
+                }
+            }
+            let detail = self.rows.get(self.selected).map(|r| r.detail.as_str()).unwrap_or("");
+            out += &format!("\x1b[{};1H{}\r\n",height-2,clip(detail, width));
+            let status = if self.message.is_empty() {
+                format!("{}  tab/arrows switch  q quit",self.tab.keys())
+            } else {
+                self.message.clone()
+            };
+            out += &format!("\x1b[2m{}\x1b[0m",clip(&status, width));
+            print!("{}",out);
+            io::stdout().flush().ok();
+        }
+
+        // runner with these flags, on the ordinary screen, until a key is pressed
+        fn runner(&mut self, args: &[&str]) {
+            let exe = env::current_exe().or_die("cannot find runner");
+            self.term.suspend();
+            println!("$ runner {}",args.join(" "));
+            let status = Command::new(&exe).args(args).status();
+            if let Err(e) = status {
+                println!("cannot run runner: {}",e);
+            }
+            print!("(press a key to go back)");
+            io::stdout().flush().ok();
+            self.term.raw();
+            read_key();
+            self.term.resume();
+            self.reload();
+        }
+
+        fn confirm(&mut self, what: &str) -> bool {
+            self.message = format!("remove {}? (y/n)",what);
+            self.draw();
+            self.message.clear();
+            read_key() == Key::Char('y')
+        }
+
+        fn act(&mut self, key: Key) {
+            let row = match self.rows.get(self.selected) {
+                Some(row) => (row.key.clone(),row.detail.clone()),
+                None => return
+            };
+            let (name,detail) = (row.0.as_str(),row.1.as_str());
+            match (self.tab,key) {
+                (Tab::Crates,Key::Char('d')) => self.runner(&["--doc",name]),
+                // the new versions are only used once the cache is rebuilt
+                (Tab::Crates,Key::Char('u')) => {
+                    self.runner(&["--update",name]);
+                    self.runner(&["--build"]);
+                },
+                (Tab::Crates,Key::Char('x')) if self.confirm(name) => self.runner(&["--remove",name]),
+                (Tab::Snippets,Key::Enter) => self.runner(&["--load",name]),
+                (Tab::Snippets,Key::Char('e')) => {
+                    self.term.suspend();
+                    platform::edit(&snippets::snippet_path(name));
+                    self.term.resume();
+                    self.reload();
+                },
+                (Tab::Snippets,Key::Char('x')) if self.confirm(name) => {
+                    snippets::remove_snippet(name);
+                    self.reload();
+                },
+                (Tab::Programs,Key::Enter) => {
+                    let program = PathBuf::from(name);
+                    match cache::recorded_source(&program) {
+                        Some(source) => {
+                            // built as it was, so -r finds it
+                            let mode = cache::recorded_mode(&program).unwrap_or_default();
+                            let mut args = vec!["-r"];
+                            if mode.starts_with("static") {
+                                args.push("-s");
+                            }
+                            if mode.ends_with("optimized") {
+                                args.push("-O");
+                            }
+                            let source = source.display().to_string();
+                            args.push(&source);
+                            self.runner(&args);
+                        },
+                        None if detail == "?" => self.message = "runner doesn't know where this program came from".into(),
+                        None => self.message = "this was an expression: 'runner -e' runs it again".into()
+                    }
+                },
+                (Tab::Programs,Key::Char('x')) => {
+                    let program = PathBuf::from(name);
+                    let stem = program.file_stem().and_then(|s| s.to_str()).unwrap_or_default().to_string();
+                    if self.confirm(&stem) {
+                        gc::remove_program(&program);
+                        self.reload();
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+
+    pub fn tui() {
+        if ! isatty::stdin_isatty() || ! isatty::stdout_isatty() {
+            es::quit("--tui needs a terminal");
+        }
+        let mut tui = Tui {
+            term: Terminal::new(),
+            tab: Tab::Crates,
+            rows: Vec::new(),
+            selected: 0,
+            top: 0,
+            message: String::new(),
+        };
+        tui.select_tab(Tab::Crates);
+        loop {
+            tui.draw();
+            let key = read_key();
+            tui.message.clear();
+            let page = tui.term.size().0.saturating_sub(5).max(1);
+            let tab = TABS.iter().position(|t| *t == tui.tab).unwrap_or(0);
+            match key {
+                Key::Quit | Key::Char('q') => break,
+                Key::Tab | Key::Right => tui.select_tab(TABS[(tab+1) % TABS.len()]),
+                Key::Left => tui.select_tab(TABS[(tab+TABS.len()-1) % TABS.len()]),
+                Key::Up | Key::Char('k') => tui.selected = tui.selected.saturating_sub(1),
+                Key::Down | Key::Char('j') => tui.selected = (tui.selected+1).min(tui.rows.len().saturating_sub(1)),
+                Key::PageUp => tui.selected = tui.selected.saturating_sub(page),
+                Key::PageDown => tui.selected = (tui.selected+page).min(tui.rows.len().saturating_sub(1)),
+                key => tui.act(key),
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+pub use unix::tui;
+
+#[cfg(not(unix))]
+pub fn tui() {
+    es::quit("--tui is only on Unix for now");
+}
@@ -0,0 +1,125 @@
+// runner --vendor copies the sources of the static cache's crates into a directory
+// with 'cargo vendor', and makes the static cache use them, so that building and
+// updating it needs no network. --export-cache and --import-cache move a static cache
+// between machines as a tarball. Its build is left out, and made again on import.
+use es::traits::*;
+use std::env;
+use std::fs;
+use std::path::{Path,PathBuf};
+use std::process::{Command,Stdio};
+
+use crate::cache;
+use crate::logging;
+
+fn absolute(path: &Path) -> PathBuf {
+    env::current_dir().or_die("cannot get current directory").join(path)
+}
+
+// as a TOML string would have it
+fn toml_path(path: &Path) -> String {
+    path.display().to_string().replace('\\',"\\\\")
+}
+
+fn config_file(static_cache: &Path) -> PathBuf {
+    static_cache.join(".cargo").join("config.toml")
+}
+
+pub fn vendor(dir: &Path) {
+    let static_cache = cache::static_cache_dir_check();
+    let dir = absolute(dir);
+    let mut c = cache::cargo_command();
+    c.arg("vendor").arg(&dir);
+    if logging::dry_run_command(&c) {
+        println!("# write the vendored sources' configuration to {}",config_file(&static_cache).display());
+        return;
+    }
+    // cargo prints the configuration which uses the vendored sources
+    let o = c.stderr(Stdio::inherit()).output().or_die("can't run cargo");
+    if ! o.status.success() {
+        es::quit("cargo vendor failed");
+    }
+    let mut config = String::from_utf8_lossy(&o.stdout).to_string();
+    // a relative path keeps working if the static cache is moved
+    if let Ok(rel) = dir.strip_prefix(&static_cache) {
+        config = config.replace(&toml_path(&dir), &toml_path(rel));
+    }
+    let config_file = config_file(&static_cache);
+    fs::create_dir_all(config_file.parent().unwrap()).or_die("cannot create static cache .cargo directory");
+    fs::write(&config_file, config).or_then_die(|e| format!("cannot write {}: {}",config_file.display(),e));
+    info!("the static cache now builds from {}",dir.display());
+}
+
+fn run(mut c: Command) -> bool {
+    if logging::dry_run_command(&c) {
+        return true;
+    }
+    trace!("{}",logging::shell_command(&c));
+    c.status().or_die("can't run tar").success()
+}
+
+// vendored sources outside the static cache aren't in the tarball
+fn outside_sources(static_cache: &Path) -> Vec<String> {
+    let config = fs::read_to_string(config_file(static_cache)).unwrap_or_default();
+    config.lines()
+        .filter_map(|line| line.trim().strip_prefix("directory"))
+        .map(|rest| rest.trim_start_matches([' ','=']).trim_matches('"').to_string())
+        .filter(|dir| Path::new(dir).is_absolute() && ! Path::new(dir).starts_with(static_cache))
+        .collect()
+}
+
+pub fn export_cache(tarball: &Path) {
+    let static_cache = cache::static_cache_dir_check();
+    let tarball = absolute(tarball);
+    let mut c = Command::new("tar");
+    // 'a' compresses according to the suffix, like .tar.gz
+    c.arg("-caf").arg(&tarball)
+        .arg(format!("--exclude={}/target",cache::STATIC_CACHE))
+        .arg("-C").arg(cache::runner_directory())
+        .arg(cache::STATIC_CACHE);
+    if ! run(c) {
+        es::quit(&format!("cannot write {}",tarball.display()));
+    }
+    for dir in outside_sources(&static_cache) {
+        info!("the vendored sources in {} are not included: 'runner --vendor' a directory in {} for that",
+            dir,static_cache.display());
+    }
+    info!("exported the static cache to {}",tarball.display());
+}
+
+// the old static cache is kept as static-cache.old
+pub fn import_cache(tarball: &Path) {
+    let tarball = absolute(tarball);
+    if ! tarball.is_file() {
+        es::quit(&format!("no such file {}",tarball.display()));
+    }
+    let home = cache::runner_directory();
+    let static_cache = cache::static_cache_dir();
+    let old = home.join(format!("{}.old",cache::STATIC_CACHE));
+    let mut c = Command::new("tar");
+    c.arg("-xf").arg(&tarball).arg("-C").arg(&home);
+    if logging::dry_run() {
+        if static_cache.exists() {
+            println!("# move {} to {}",static_cache.display(),old.display());
+        }
+        run(c);
+        return;
+    }
+    let existed = static_cache.exists();
+    if existed {
+        let _ = fs::remove_dir_all(&old);
+        fs::rename(&static_cache, &old).or_die("cannot move the old static cache aside");
+    }
+    if ! run(c) || ! static_cache.join("Cargo.toml").is_file() {
+        let _ = fs::remove_dir_all(&static_cache);
+        if existed {
+            fs::rename(&old, &static_cache).or_die("cannot put back the old static cache");
+        }
+        es::quit(&format!("{} does not have a static cache (made by 'runner --export-cache')",tarball.display()));
+    }
+    if existed {
+        info!("the old static cache is now {}",old.display());
+    }
+    // the metadata has paths for the other machine
+    env::set_current_dir(&static_cache).or_die("static cache wasn't a directory?");
+    cache::build_static_cache();
+}
@@ -0,0 +1,63 @@
+// runner --verify checks the program's output against '//=>' comments in the
+// snippet, like doctests do. Each comment is one line of output, in order:
+//
+//    println!("{}", 2 + 2);   //=> 4
+//    println!("{:?}", v);
+//    //=> [1, 2, 3]
+use es::traits::*;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use crate::snapshot;
+use crate::strutil;
+
+const MARKER: &str = "//=>";
+
+// the expected lines, with the line of the snippet they come from. A '//=>' in
+// a string is not one
+fn expectations(source: &str) -> Vec<(usize,String)> {
+    let masked = strutil::blank_literals(source, false);
+    source.lines().zip(masked.lines()).enumerate()
+        .filter_map(|(i,(line,code))| code.find(MARKER).map(|idx| (i+1, line[idx+MARKER.len()..].trim().to_string())))
+        .collect()
+}
+
+// returns the exit code: 1 if the output isn't what was expected
+pub fn run(builder: &mut Command, file: &Path) -> i32 {
+    let source = fs::read_to_string(file).or_then_die(|e| format!("cannot read {}: {}",file.display(),e));
+    let expected = expectations(&source);
+    if expected.is_empty() {
+        es::quit(&format!("--verify: {} has no '{}' comments",file.display(),MARKER));
+    }
+    let output = match snapshot::capture(builder) {
+        Ok(output) => output,
+        Err(code) => return code
+    };
+    let actual: Vec<&str> = output.lines().map(str::trim_end).collect();
+    let mut mismatches = 0;
+    for (k,(lineno,want)) in expected.iter().enumerate() {
+        match actual.get(k) {
+            Some(got) if got == want => (),
+            Some(got) => {
+                println!("{}:{}: expected '{}', got '{}'",file.display(),lineno,want,got);
+                mismatches += 1;
+            },
+            None => {
+                println!("{}:{}: expected '{}', but there was no more output",file.display(),lineno,want);
+                mismatches += 1;
+            }
+        }
+    }
+    for extra in actual.iter().skip(expected.len()) {
+        println!("{}: unexpected output '{}'",file.display(),extra);
+        mismatches += 1;
+    }
+    if mismatches > 0 {
+        info!("the output did not match in {} place{}",mismatches,if mismatches == 1 {""} else {"s"});
+        1
+    } else {
+        info!("all {} expected lines matched",expected.len());
+        0
+    }
+}